@@ -0,0 +1,98 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "embed-frontend")]
+    embed_frontend::run();
+}
+
+/// Writes `assets.rs` to `OUT_DIR`, with an `Asset` for each file of the built frontend.
+/// Each file is also served at a path containing its hash, and `index.html` refers to those paths,
+/// so browsers can cache them forever.
+#[cfg(feature = "embed-frontend")]
+mod embed_frontend {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write as _;
+    use std::io::Write as _;
+    use std::path::{Path, PathBuf};
+
+    /// Paths relative to `frontend/static`, as `index.html` refers to them
+    const FILES: [(&str, &str); 3] = [
+        ("style.css", "text/css; charset=utf-8"),
+        ("target/wasm.js", "text/javascript; charset=utf-8"),
+        ("target/wasm_bg.wasm", "application/wasm"),
+    ];
+
+    pub fn run() {
+        let manifest_directory = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap());
+        let root = manifest_directory.parent().unwrap();
+        let out_directory = PathBuf::from(std::env::var_os("OUT_DIR").unwrap());
+
+        let mut index = String::from_utf8(read(&root.join("index.html"))).expect("index.html isn't UTF-8");
+        let mut files = String::new();
+        for (path, content_type) in FILES {
+            let contents = read(&root.join("frontend/static").join(path));
+            let hash = hash(&contents);
+            let hashed_path = format!("assets/{}/{}", hash, path);
+            index = index.replace(&format!("\"{}\"", path), &format!("\"/{}\"", hashed_path));
+            let asset = write_asset(&out_directory, path, content_type, &hash, &contents);
+            writeln!(files, "    ({:?}, {:?}, {}),", path, hashed_path, asset).unwrap();
+        }
+        let index_hash = hash(index.as_bytes());
+        let index = write_asset(&out_directory, "index.html", "text/html; charset=utf-8", &index_hash, index.as_bytes());
+
+        let code = format!(
+            "/// Path, path with the hash, and the file\n\
+             pub static FILES: &[(&str, &str, Asset)] = &[\n{}];\n\
+             pub static INDEX: Asset = {};\n",
+            files,
+            index,
+        );
+        std::fs::write(out_directory.join("assets.rs"), code).unwrap();
+    }
+
+    fn read(path: &Path) -> Vec<u8> {
+        println!("cargo:rerun-if-changed={}", path.display());
+        std::fs::read(path).unwrap_or_else(|error| {
+            panic!("can't read {} ({}); build the frontend before enabling `embed-frontend`", path.display(), error)
+        })
+    }
+
+    fn hash(contents: &[u8]) -> String {
+        let digest = format!("{:x}", Sha256::digest(contents));
+        digest[..16].to_owned()
+    }
+
+    /// Returns the `Asset` expression. Compressed variants are only kept when they're smaller.
+    fn write_asset(out_directory: &Path, path: &str, content_type: &str, hash: &str, contents: &[u8]) -> String {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(contents).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let mut brotli = Vec::new();
+        {
+            let mut writer = brotli::CompressorWriter::new(&mut brotli, 4096, 11, 22);
+            writer.write_all(contents).unwrap();
+        }
+
+        let name = path.replace('/', "_");
+        let include = |extension: &str, variant: &[u8]| {
+            let file = out_directory.join(format!("{}{}", name, extension));
+            std::fs::write(&file, variant).unwrap();
+            format!("include_bytes!({:?})", file.display().to_string())
+        };
+        let identity = include("", contents);
+        let compressed = |extension: &str, variant: &[u8]| {
+            if variant.len() < contents.len() {
+                format!("Some({})", include(extension, variant))
+            } else {
+                "None".to_owned()
+            }
+        };
+        let gzip = compressed(".gz", &gzip);
+        let brotli = compressed(".br", &brotli);
+
+        format!(
+            "Asset {{ content_type: {:?}, hash: {:?}, identity: {}, gzip: {}, brotli: {} }}",
+            content_type, hash, identity, gzip, brotli,
+        )
+    }
+}
@@ -1,12 +1,225 @@
 use crate::database::{Id};
-use crate::font::{Font};
+use crate::font::{self, Font};
 use deku::prelude::*;
+use serde::{Deserialize};
 use shared::glyph::{Glyph};
+use shared::util::{char_map, char_write, read_rest};
+use std::str::{FromStr};
+use std::time::{Duration};
+
+/// A test ends after this many wrong keystrokes even if the correct characters weren't typed.
+pub const MAX_MISTAKES: usize = 8;
+
+/// Times shorter than this, in milliseconds, are faster than anyone can react, so they come from broken or forged clients.
+pub const MIN_TIME: f64 = 50.0;
+
+/// The number of glyphs to choose from in `TestKind::MultipleChoice`
+pub const CHOICE_COUNT: usize = 4;
+
+/// The shortest and longest words in `TestKind::Word`
+pub const WORD_LENGTH: std::ops::RangeInclusive<usize> = 3..=5;
+
+/// The blur radius in `TestKind::Blurred`, relative to the glyph's height
+pub const BLUR: f32 = 0.04;
+
+/// The glyph height in CSS pixels in `TestKind::Small`
+pub const SMALL_SIZE: u32 = 12;
 
 // A "test" begins when a glyph is shown to the user, and usually ends when the correct character is typed
 #[derive(DekuRead, DekuWrite)]
 #[deku(endian = "big")]
 pub struct ActiveTest {
     pub font: Id<Font>,
+    // Results are ignored if the font has moved on to a newer version
+    pub font_version: Id<font::Version>,
     pub glyph: Id<Glyph>,
+    pub kind: TestKind,
+    /// Milliseconds since the Unix epoch when the test was shown
+    pub served: u64,
+    /// Milliseconds since the Unix epoch. After this, the test is removed as if the user never started it.
+    pub expires: u64,
+    option_count: u8,
+    #[deku(count = "option_count")]
+    options: Vec<Id<Glyph>>,
+    // Every character typed so far, including wrong ones
+    #[deku(reader = "read_rest(deku::rest, deku::ctx::Endian::Big)")]
+    pub keystrokes: Vec<Keystroke>,
+}
+
+/// What happens to the tested candidate when a test expires
+#[derive(Clone, Copy)]
+pub enum Expiry {
+    /// The candidate moves to the front of the queue, so the next test shows it again
+    Requeue,
+    /// The candidate leaves the queue, and is ranked with the trials it already has
+    Discard,
+}
+
+/// How a glyph is presented and answered. Each font uses one kind of test.
+#[derive(DekuRead, DekuWrite, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[deku(type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
+#[serde(rename_all = "kebab-case")]
+pub enum TestKind {
+    /// Type the glyph's character
+    #[deku(id = "0")]
+    Single,
+    /// Choose the glyph for a character among glyphs for other characters
+    #[deku(id = "1")]
+    MultipleChoice,
+    /// Type a short made-up word that includes the glyph
+    #[deku(id = "2")]
+    Word,
+    /// Like `Single`, but blurred by `BLUR`
+    #[deku(id = "3")]
+    Blurred,
+    /// Like `Single`, but `SMALL_SIZE` pixels tall
+    #[deku(id = "4")]
+    Small,
+}
+
+#[derive(DekuRead, DekuWrite, Deserialize, Clone, Copy)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
+pub struct Keystroke {
+    #[deku(map = "char_map", writer = "char_write(deku::output, char)")]
+    pub char: char,
+    /// Milliseconds between showing the glyph and typing `char`
+    pub time: f64,
+}
+
+/// What a finished test says about the tested glyph
+pub struct Outcome {
+    /// Milliseconds used as the glyph's time, or `None` if the glyph was misread
+    pub time: Option<f64>,
+    /// Characters that were typed or chosen instead of the glyph's character, each at most once, so a glyph can't be confused with a character in more trials than it has
+    pub confusions: Vec<char>,
+}
+
+impl ActiveTest {
+    /// `options` are the glyphs shown with the tested glyph, in order, including the tested glyph. Only `TestKind::MultipleChoice` and `TestKind::Word` use them. The test expires `lifetime` after `served`.
+    pub fn new(
+        font: Id<Font>,
+        font_version: Id<font::Version>,
+        glyph: Id<Glyph>,
+        kind: TestKind,
+        options: Vec<Id<Glyph>>,
+        served: u64,
+        lifetime: Duration,
+    ) -> Self {
+        ActiveTest {
+            font,
+            font_version,
+            glyph,
+            kind,
+            served,
+            expires: served + lifetime.as_millis() as u64,
+            option_count: options.len() as u8,
+            options,
+            keystrokes: Vec::new(),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires
+    }
+
+    pub fn options(&self) -> &[Id<Glyph>] {
+        &self.options
+    }
+
+    /// The index of the tested glyph in the characters that must be typed
+    pub fn position(&self) -> usize {
+        self.options.iter().position(|&id| id == self.glyph).unwrap_or(0)
+    }
+
+    /// Scores `Single`, `Blurred` and `Small` tests, where `expected` only contains the glyph's character, and `Word` tests. Returns `None` if more keystrokes are needed.
+    ///
+    /// For words, the time is divided by the word's length, and only mistakes at the tested glyph's position count against it.
+    pub fn score_typing(&self, expected: &[char]) -> Option<Outcome> {
+        let position = self.position();
+        let mut typed = 0;
+        let mut mistakes = 0;
+        let mut confusions = Vec::new();
+
+        for keystroke in &self.keystrokes {
+            match expected.get(typed) {
+                Some(&char) if char == keystroke.char => typed += 1,
+                Some(_) => {
+                    mistakes += 1;
+                    if typed == position && !confusions.contains(&keystroke.char) {
+                        confusions.push(keystroke.char);
+                    }
+                },
+                None => break,
+            }
+        }
+
+        let finished = typed == expected.len();
+        if !finished && mistakes < MAX_MISTAKES {
+            return None;
+        }
+
+        Some(Outcome {
+            time: match (finished, confusions.is_empty(), self.keystrokes.last()) {
+                (true, true, Some(last)) => Some(last.time / expected.len() as f64),
+                _ => None,
+            },
+            confusions,
+        })
+    }
+
+    /// Scores `MultipleChoice` tests. `chosen_char` is the character of the chosen glyph, if `index` is valid.
+    pub fn score_choice(&self, index: usize, chosen_char: Option<char>, time: f64) -> Outcome {
+        if self.options.get(index) == Some(&self.glyph) {
+            Outcome {
+                time: Some(time),
+                confusions: Vec::new(),
+            }
+        } else {
+            Outcome {
+                time: None,
+                confusions: chosen_char.into_iter().collect(),
+            }
+        }
+    }
+}
+
+impl FromStr for Expiry {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "requeue" => Ok(Expiry::Requeue),
+            "discard" => Ok(Expiry::Discard),
+            _ => Err(format!("unknown test expiry \"{}\"", string)),
+        }
+    }
+}
+
+impl TestKind {
+    /// The name used in JSON and by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            TestKind::Single => "single",
+            TestKind::MultipleChoice => "multiple-choice",
+            TestKind::Word => "word",
+            TestKind::Blurred => "blurred",
+            TestKind::Small => "small",
+        }
+    }
+}
+
+impl FromStr for TestKind {
+    type Err = String;
+
+    /// Uses the same names as JSON
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "single" => Ok(TestKind::Single),
+            "multiple-choice" => Ok(TestKind::MultipleChoice),
+            "word" => Ok(TestKind::Word),
+            "blurred" => Ok(TestKind::Blurred),
+            "small" => Ok(TestKind::Small),
+            _ => Err(format!("unknown test kind \"{}\"", string)),
+        }
+    }
 }
@@ -0,0 +1,123 @@
+// The frontend's files. With the `embed-frontend` feature they're included in the binary by `build.rs`,
+// and otherwise read from the directories in the config.
+
+use crate::config::{Config};
+use crate::error::{InitError};
+use tide::{Body, Response, StatusCode};
+use tide::http::headers::{HeaderName, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, VARY};
+
+#[cfg(feature = "embed-frontend")]
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+/// Paths containing a hash change whenever the file does
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Browsers must check the ETag before using a cached copy
+const REVALIDATE: &str = "no-cache";
+
+/// A file with variants precompressed by `build.rs`
+pub struct Asset {
+    pub content_type: &'static str,
+    /// Start of the SHA-256 of the file, in hex
+    pub hash: &'static str,
+    pub identity: &'static [u8],
+    pub gzip: Option<&'static [u8]>,
+    pub brotli: Option<&'static [u8]>,
+}
+
+impl Asset {
+    /// Each encoding needs its own ETag, since it's a different sequence of bytes
+    fn etag(&self, encoding: Option<&str>) -> String {
+        match encoding {
+            Some(encoding) => format!("\"{}-{}\"", self.hash, encoding),
+            None => format!("\"{}\"", self.hash),
+        }
+    }
+
+    /// Chooses the smallest variant that the client accepts
+    fn negotiate(&self, accept_encoding: &str) -> (Option<&'static str>, &'static [u8]) {
+        if let Some(brotli) = self.brotli.filter(|_| accepts(accept_encoding, "br")) {
+            (Some("br"), brotli)
+        } else if let Some(gzip) = self.gzip.filter(|_| accepts(accept_encoding, "gzip")) {
+            (Some("gzip"), gzip)
+        } else {
+            (None, self.identity)
+        }
+    }
+
+    /// Responds with 304 Not Modified if the client has any variant, since they all have the same content
+    pub fn respond(&self, accept_encoding: &str, if_none_match: &str, immutable: bool) -> Response {
+        let (encoding, contents) = self.negotiate(accept_encoding);
+        let not_modified = if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag == "*" || [None, Some("br"), Some("gzip")].iter().any(|&encoding| tag == self.etag(encoding))
+        });
+
+        let mut response = Response::new(if not_modified { StatusCode::NotModified } else { StatusCode::Ok });
+        response.insert_header(ETAG, self.etag(encoding));
+        response.insert_header(CACHE_CONTROL, if immutable { IMMUTABLE } else { REVALIDATE });
+        response.insert_header(VARY, "Accept-Encoding");
+        if !not_modified {
+            response.insert_header(CONTENT_TYPE, self.content_type);
+            if let Some(encoding) = encoding {
+                response.insert_header(CONTENT_ENCODING, encoding);
+            }
+            response.set_body(Body::from_reader(async_std::io::Cursor::new(contents), Some(contents.len())));
+        }
+        response
+    }
+}
+
+/// `q=0` refuses an encoding, and an explicit entry overrides `*`
+fn accepts(accept_encoding: &str, coding: &str) -> bool {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(1.0, |quality| quality.trim().parse().unwrap_or(0.0));
+        if name.eq_ignore_ascii_case(coding) {
+            return quality > 0.0;
+        } else if name == "*" {
+            wildcard = Some(quality > 0.0);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+fn header<S>(req: &tide::Request<S>, name: HeaderName) -> String {
+    req.header(name).map_or(String::new(), |values| values.iter().map(|value| value.as_str()).collect::<Vec<_>>().join(","))
+}
+
+/// Serves `asset` at `path`
+pub fn serve<S: Clone + Send + Sync + 'static>(server: &mut tide::Server<S>, path: &str, asset: &'static Asset, immutable: bool) {
+    server.at(path).get(move |req: tide::Request<S>| async move {
+        let accept_encoding = header(&req, tide::http::headers::ACCEPT_ENCODING);
+        let if_none_match = header(&req, tide::http::headers::IF_NONE_MATCH);
+        Ok(asset.respond(&accept_encoding, &if_none_match, immutable))
+    });
+}
+
+/// The embedded files are also served at their usual paths, for pages that were loaded before an update
+#[cfg(feature = "embed-frontend")]
+pub fn init<S: Clone + Send + Sync + 'static>(server: &mut tide::Server<S>, _config: &Config) -> Result<(), InitError> {
+    for (path, hashed_path, asset) in FILES {
+        serve(server, &format!("/{}", path), asset, false);
+        serve(server, &format!("/{}", hashed_path), asset, true);
+    }
+    serve(server, "/", &INDEX, false);
+    serve(server, "/*", &INDEX, false);
+    Ok(())
+}
+
+#[cfg(not(feature = "embed-frontend"))]
+pub fn init<S: Clone + Send + Sync + 'static>(server: &mut tide::Server<S>, config: &Config) -> Result<(), InitError> {
+    let static_directory = &config.static_directory;
+    server.at("/style.css").serve_file(static_directory.join("style.css"))?;
+    server.at("/target/wasm.js").serve_file(static_directory.join("target/wasm.js"))?;
+    server.at("/target/wasm_bg.wasm").serve_file(static_directory.join("target/wasm_bg.wasm"))?;
+    server.at("/").serve_file(&config.index_file)?;
+    server.at("/*").serve_file(&config.index_file)?;
+    Ok(())
+}
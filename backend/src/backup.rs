@@ -0,0 +1,147 @@
+// A portable copy of every tree, made by `Database::backup` and read by `Database::restore`.
+//
+// Layout, with big-endian integers:
+// - `Header`
+// - For each tree: `TreeHeader`, its `Item`s, then a `Checksum` of the tree header and items
+// - A `Checksum` of everything before it
+
+use crate::error::{Error as E};
+use crate::storage::{Snapshot};
+use deku::prelude::*;
+
+const MAGIC: [u8; 8] = *b"FONTGEN\0";
+
+/// Increased when the layout changes. Archives with a newer version can't be restored.
+pub const FORMAT_VERSION: u16 = 1;
+
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+struct Header {
+    magic: [u8; 8],
+    format_version: u16,
+    /// Milliseconds since the Unix epoch
+    created: u64,
+    next_id: u64,
+    tree_count: u32,
+}
+
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+struct TreeHeader {
+    name_length: u16,
+    #[deku(count = "name_length")]
+    name: Vec<u8>,
+    item_count: u64,
+}
+
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+struct Item {
+    key_length: u32,
+    #[deku(count = "key_length")]
+    key: Vec<u8>,
+    value_length: u32,
+    #[deku(count = "value_length")]
+    value: Vec<u8>,
+}
+
+/// CRC-32 of the bytes before it, since the last checksum for a tree
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+struct Checksum {
+    crc: u32,
+}
+
+/// What an archive contains
+pub struct Summary {
+    pub created: u64,
+    pub trees: usize,
+    pub items: usize,
+}
+
+pub fn write(snapshot: &Snapshot) -> Result<Vec<u8>, E> {
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64);
+    let mut output = Header {
+        magic: MAGIC,
+        format_version: FORMAT_VERSION,
+        created,
+        next_id: snapshot.next_id,
+        tree_count: snapshot.trees.len() as u32,
+    }.to_bytes()?;
+
+    for (name, items) in &snapshot.trees {
+        let start = output.len();
+        output.extend(TreeHeader {
+            name_length: name.len() as u16,
+            name: name.clone(),
+            item_count: items.len() as u64,
+        }.to_bytes()?);
+        for (key, value) in items {
+            output.extend(Item {
+                key_length: key.len() as u32,
+                key: key.clone(),
+                value_length: value.len() as u32,
+                value: value.clone(),
+            }.to_bytes()?);
+        }
+        output.extend(Checksum { crc: crc32fast::hash(&output[start..]) }.to_bytes()?);
+    }
+
+    output.extend(Checksum { crc: crc32fast::hash(&output) }.to_bytes()?);
+    Ok(output)
+}
+
+/// Checks every checksum before returning anything, so a damaged archive is never partly restored.
+pub fn read(input: &[u8]) -> Result<(Snapshot, Summary), E> {
+    let body_length = input
+        .len()
+        .checked_sub(4)
+        .ok_or_else(|| E::invalid_backup("it's too short"))?;
+    let (_, checksum) = Checksum::from_bytes((&input[body_length..], 0))?;
+    let ((mut rest, _), header) = Header::from_bytes((&input[..body_length], 0))
+        .map_err(|_| E::invalid_backup("the header is incomplete"))?;
+    if header.magic != MAGIC {
+        return Err(E::invalid_backup("it's not a backup of this server"));
+    }
+    if header.format_version > FORMAT_VERSION {
+        return Err(E::invalid_backup(&format!("its format version is {}, but this server only supports up to {}", header.format_version, FORMAT_VERSION)));
+    }
+    if checksum.crc != crc32fast::hash(&input[..body_length]) {
+        return Err(E::invalid_backup("the checksum doesn't match"));
+    }
+
+    let mut trees = Vec::with_capacity(header.tree_count as usize);
+    let mut item_count = 0;
+    for _ in 0..header.tree_count {
+        let start = rest;
+        let ((after_header, _), tree) = TreeHeader::from_bytes((rest, 0))?;
+        rest = after_header;
+        let mut items = Vec::new();
+        for _ in 0..tree.item_count {
+            let ((after_item, _), item) = Item::from_bytes((rest, 0))?;
+            rest = after_item;
+            items.push((item.key, item.value));
+        }
+        let tree_length = start.len() - rest.len();
+        let ((after_checksum, _), checksum) = Checksum::from_bytes((rest, 0))?;
+        rest = after_checksum;
+        if checksum.crc != crc32fast::hash(&start[..tree_length]) {
+            return Err(E::invalid_backup(&format!("the checksum of tree \"{}\" doesn't match", String::from_utf8_lossy(&tree.name))));
+        }
+
+        item_count += items.len();
+        trees.push((tree.name, items));
+    }
+    if !rest.is_empty() {
+        return Err(E::invalid_backup("it has extra bytes after the last tree"));
+    }
+
+    let summary = Summary {
+        created: header.created,
+        trees: trees.len(),
+        items: item_count,
+    };
+    Ok((Snapshot { next_id: header.next_id, trees }, summary))
+}
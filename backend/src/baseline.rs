@@ -0,0 +1,66 @@
+use deku::prelude::*;
+use std::str::{FromStr};
+
+/// A user's reaction times for control glyphs, which come from a reference font that doesn't evolve.
+#[derive(DekuRead, DekuWrite, Default)]
+#[deku(endian = "big")]
+pub struct Baseline {
+    pub count: u32,
+    pub mean: f64,
+    /// Used to calculate the variance without storing every time (Welford's algorithm)
+    sum_of_squared_deviations: f64,
+}
+
+/// How a time is compared with the user's `Baseline` before it's used for fitness.
+#[derive(Clone, Copy)]
+pub enum Normalization {
+    /// Times are used as they are, in milliseconds
+    None,
+    /// Times are divided by the user's mean time, so 1.0 is typical for the user
+    Ratio,
+    /// The number of standard deviations from the user's mean time
+    ZScore,
+}
+
+impl Baseline {
+    pub fn add(&mut self, time: f64) {
+        self.count += 1;
+        let deviation = time - self.mean;
+        self.mean += deviation / f64::from(self.count);
+        self.sum_of_squared_deviations += deviation * (time - self.mean);
+    }
+
+    pub fn standard_deviation(&self) -> Option<f64> {
+        if self.count < 2 {
+            return None;
+        }
+        Some((self.sum_of_squared_deviations / f64::from(self.count - 1)).sqrt())
+    }
+}
+
+/// Parses `none`, `ratio` or `z-score`.
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "none" => Ok(Normalization::None),
+            "ratio" => Ok(Normalization::Ratio),
+            "z-score" => Ok(Normalization::ZScore),
+            _ => Err(format!("unknown normalization \"{}\"", string)),
+        }
+    }
+}
+
+impl Normalization {
+    /// Returns `time` unchanged if the baseline doesn't have enough times.
+    pub fn apply(&self, time: f64, baseline: &Baseline) -> f64 {
+        match (self, baseline.standard_deviation()) {
+            (Normalization::Ratio, _) if baseline.count > 0 && baseline.mean > 0.0 =>
+                time / baseline.mean,
+            (Normalization::ZScore, Some(deviation)) if deviation > 0.0 =>
+                (time - baseline.mean) / deviation,
+            _ => time,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// Commands for operators. The server must be stopped first, because sled locks the data directory.
+
+use backend::config::{Config};
+use backend::database::{Database, Id};
+use backend::error::{InitError};
+use backend::state::{State};
+use backend::worker;
+use shared::glyph::{Glyph};
+use shared::util::{DekuRW};
+use std::path::{Path};
+use std::str::{FromStr};
+
+const USAGE: &str = "\
+usage: admin [FLAGS] COMMAND
+
+The flags and config file are the same as the server's.
+
+commands:
+    fonts                           list fonts
+    history FONT                    list a font's versions, from the current one back
+    candidates FONT                 list the glyphs competing in a font's current version
+    glyph-svg GLYPH                 print a glyph as SVG
+    import DIRECTORY                store the .glyph files in a directory, in order of name
+    create-font TEST_KIND GLYPH...  add a font whose first version has the stored glyphs
+    reset-scores FONT               delete the test results of a font's current version
+    delete-user NAME                delete a user
+    gc [--dry-run]                  delete unreachable versions and glyphs
+    verify [--repair]               check that every item can be read and refers to items that exist
+    backup FILE                     save every tree in a file
+    restore FILE                    load a backup into an empty data directory";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(error) = async_std::task::block_on(run(&args)) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: &[String]) -> Result<(), InitError> {
+    let (config, args) = Config::load(args)?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let args = &args[..];
+    let db = Database::open(&config.data_directory, config.storage_mode).await?;
+    // `restore` must run before `State::new` adds anything, such as index trees
+    if let ["restore", path] = args {
+        let archive = async_std::fs::read(path).await?;
+        let summary = db.restore(&archive).await?;
+        println!("Restored {} items in {} trees from a backup made at {} ms since the Unix epoch", summary.items, summary.trees, summary.created);
+        return Ok(());
+    }
+
+    let (worker, _) = worker::channel(config.worker);
+    let state = State::new(db, config.fitness, config.evaluator, worker, config.passwords).await?;
+
+    match args {
+        ["fonts"] => {
+            for (id, font) in state.list_fonts().await? {
+                println!("font {}: current version {}, {} queued candidates", id, font.current_version, font.candidates.len());
+            }
+        },
+        ["history", font] => {
+            for version in state.font_history(parse(font)?).await? {
+                let parent = version.parent.map_or("none".to_owned(), |parent| parent.to_string());
+                println!("version {}: parent {}, created at {}, seed {}", version.id, parent, version.created, version.seed);
+                for glyph in version.glyphs {
+                    let fitness = glyph.fitness.map_or("none".to_owned(), |fitness| fitness.to_string());
+                    println!("    {:?}: glyph {}, fitness {}", glyph.char, glyph.glyph, fitness);
+                }
+            }
+        },
+        ["candidates", font] => {
+            for candidate in state.font_candidates(parse(font)?).await? {
+                let parent = candidate.parent.map_or("none".to_owned(), |parent| parent.to_string());
+                let median_time = candidate.median_time.map_or("none".to_owned(), |time| time.to_string());
+                let queued = if candidate.queued { ", queued" } else { "" };
+                println!("glyph {} {:?}: parent {}, {} trials, {} errors, median time {}{}", candidate.glyph, candidate.char, parent, candidate.trials, candidate.errors, median_time, queued);
+            }
+        },
+        ["glyph-svg", glyph] => {
+            let glyph = state.get_glyph(parse(glyph)?).await?;
+            println!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 32767 32767\"><path fill-rule=\"evenodd\" d=\"{}\"/></svg>",
+                glyph.to_svg_path_d(),
+            );
+        },
+        ["import", directory] => {
+            let glyphs = read_glyphs(Path::new(directory))?;
+            for (id, glyph) in state.import_glyphs(&glyphs).await?.into_iter().zip(&glyphs) {
+                println!("glyph {} {:?}", id, glyph.char);
+            }
+        },
+        ["create-font", test_kind, glyphs @ ..] if !glyphs.is_empty() => {
+            let glyphs = glyphs.iter().map(|glyph| parse(glyph)).collect::<Result<Vec<Id<Glyph>>, _>>()?;
+            let id = state.add_font_with_glyphs(&glyphs, test_kind.parse().map_err(InitError::Config)?).await?;
+            println!("font {}", id);
+        },
+        ["reset-scores", font] => {
+            let requeued = state.reset_scores(parse(font)?).await?;
+            println!("Queued {} tested candidates again", requeued);
+        },
+        ["delete-user", name] => {
+            state.delete_user(name).await?;
+            println!("Deleted user \"{}\"", name);
+        },
+        ["gc", options @ ..] if matches!(options, [] | ["--dry-run"]) => {
+            let report = state.collect_garbage(!options.is_empty()).await?;
+            let verb = if report.dry_run { "Would delete" } else { "Deleted" };
+            println!("{} {} versions, {} glyphs and {} other records", verb, report.versions, report.glyphs, report.records);
+        },
+        ["verify", options @ ..] if matches!(options, [] | ["--repair"]) => {
+            let report = state.verify(!options.is_empty()).await?;
+            for problem in &report.problems {
+                let repaired = if problem.repaired { " (repaired)" } else { "" };
+                println!("{}: {}: {}{}", problem.tree, problem.item, problem.message, repaired);
+            }
+            let repaired = report.problems.iter().filter(|problem| problem.repaired).count();
+            println!("Checked {} items, found {} problems and repaired {}", report.items, report.problems.len(), repaired);
+        },
+        ["backup", path] => {
+            async_std::fs::write(path, state.backup().await?).await?;
+            println!("Saved backup to {}", path);
+        },
+        _ => return Err(InitError::Config(USAGE.to_owned())),
+    }
+
+    Ok(())
+}
+
+fn parse<T: FromStr>(string: &str) -> Result<T, InitError> {
+    string.parse().map_err(|_| InitError::Config(format!("invalid ID \"{}\"", string)))
+}
+
+/// Each file contains one glyph, encoded like the glyphs that the server stores.
+fn read_glyphs(directory: &Path) -> Result<Vec<Glyph>, InitError> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "glyph") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .iter()
+        .map(|path| {
+            <Glyph as DekuRW>::read(&std::fs::read(path)?)
+                .map_err(|error| InitError::Config(format!("{}: {}", path.display(), error)))
+        })
+        .collect()
+}
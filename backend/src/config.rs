@@ -0,0 +1,454 @@
+// Settings for the server and the admin tool. Each setting can come from the config file, an environment variable or a command-line flag, and later sources override earlier ones.
+
+use crate::error::{InitError};
+use crate::{evaluator, fitness, rate_limit, storage, user, worker};
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::{FromStr};
+use std::time::{Duration};
+
+/// Read if it exists, unless `--config` or the `CONFIG` environment variable names another file
+pub const DEFAULT_FILE: &str = "fontgen.toml";
+/// Longer durations are surely mistakes, and would overflow the timestamps they're added to
+const MAX_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+pub struct Config {
+    pub address: String,
+    pub data_directory: PathBuf,
+    pub storage_mode: storage::Mode,
+    /// Contains `style.css` and the `target` directory from building the frontend.
+    /// Not used when the frontend is embedded with the `embed-frontend` feature.
+    pub static_directory: PathBuf,
+    /// Served for every other path, so the frontend can route it. Not used when the frontend is embedded.
+    pub index_file: PathBuf,
+    /// Names of the users who can change fonts' test kinds and versions, evolve fonts offline, and control the worker and garbage collection over HTTP. Separated by commas in environment variables and flags.
+    pub operators: Vec<String>,
+    /// Addresses that can request `/metrics`, which counts every tree's items. Separated by commas like `operators`.
+    pub metrics_clients: Vec<IpAddr>,
+    /// Used for new password hashes
+    pub passwords: user::HashParams,
+    pub fitness: fitness::Config,
+    pub evaluator: evaluator::Config,
+    pub worker: worker::Config,
+    /// Limits starting and submitting tests
+    pub rate_limit: rate_limit::Config,
+}
+
+struct Setting {
+    /// The name in the config file, such as `server.address` for `address` in the `[server]` table
+    key: &'static str,
+    env: &'static str,
+    /// Used as `--flag value` or `--flag=value`
+    flag: &'static str,
+    apply: fn(&mut Config, &str) -> Result<(), String>,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "server.address",
+        env: "ADDRESS",
+        flag: "address",
+        apply: |config, value| set_string(&mut config.address, value),
+    },
+    Setting {
+        key: "server.data_directory",
+        env: "DATA_DIRECTORY",
+        flag: "data-directory",
+        apply: |config, value| set_path(&mut config.data_directory, value),
+    },
+    Setting {
+        key: "server.storage_mode",
+        env: "STORAGE_MODE",
+        flag: "storage-mode",
+        apply: |config, value| set(&mut config.storage_mode, value),
+    },
+    Setting {
+        key: "server.static_directory",
+        env: "STATIC_DIRECTORY",
+        flag: "static-directory",
+        apply: |config, value| set_path(&mut config.static_directory, value),
+    },
+    Setting {
+        key: "server.index_file",
+        env: "INDEX_FILE",
+        flag: "index-file",
+        apply: |config, value| set_path(&mut config.index_file, value),
+    },
+    Setting {
+        key: "server.operators",
+        env: "OPERATORS",
+        flag: "operators",
+        apply: |config, value| set_list(&mut config.operators, value),
+    },
+    Setting {
+        key: "server.metrics_clients",
+        env: "METRICS_CLIENTS",
+        flag: "metrics-clients",
+        apply: |config, value| set_addresses(&mut config.metrics_clients, value),
+    },
+    Setting {
+        key: "passwords.memory_cost",
+        env: "PASSWORD_MEMORY_COST",
+        flag: "password-memory-cost",
+        apply: |config, value| set_number(&mut config.passwords.memory_cost, value),
+    },
+    Setting {
+        key: "passwords.time_cost",
+        env: "PASSWORD_TIME_COST",
+        flag: "password-time-cost",
+        apply: |config, value| set_number(&mut config.passwords.time_cost, value),
+    },
+    Setting {
+        key: "passwords.lanes",
+        env: "PASSWORD_LANES",
+        flag: "password-lanes",
+        apply: |config, value| set_number(&mut config.passwords.lanes, value),
+    },
+    Setting {
+        key: "evolution.selection_rule",
+        env: "SELECTION_RULE",
+        flag: "selection-rule",
+        apply: |config, value| set(&mut config.fitness.rule, value),
+    },
+    Setting {
+        key: "evolution.min_trials",
+        env: "MIN_TRIALS",
+        flag: "min-trials",
+        apply: |config, value| set_number(&mut config.fitness.min_trials, value),
+    },
+    Setting {
+        key: "evolution.error_penalty",
+        env: "ERROR_PENALTY",
+        flag: "error-penalty",
+        apply: |config, value| set_number(&mut config.fitness.error_penalty, value),
+    },
+    Setting {
+        key: "evolution.confusion_penalty",
+        env: "CONFUSION_PENALTY",
+        flag: "confusion-penalty",
+        apply: |config, value| set_number(&mut config.fitness.confusion_penalty, value),
+    },
+    Setting {
+        key: "evolution.normalization",
+        env: "NORMALIZATION",
+        flag: "normalization",
+        apply: |config, value| set(&mut config.fitness.normalization, value),
+    },
+    Setting {
+        key: "evolution.reference_font",
+        env: "REFERENCE_FONT",
+        flag: "reference-font",
+        apply: |config, value| {
+            config.fitness.reference_font = match value {
+                "none" => None,
+                _ => Some(value.parse().map_err(|_| format!("invalid font ID \"{}\"", value))?),
+            };
+            Ok(())
+        },
+    },
+    Setting {
+        key: "evolution.min_controls",
+        env: "MIN_CONTROLS",
+        flag: "min-controls",
+        apply: |config, value| set_number(&mut config.fitness.min_controls, value),
+    },
+    Setting {
+        key: "evolution.control_rate",
+        env: "CONTROL_RATE",
+        flag: "control-rate",
+        apply: |config, value| set_number(&mut config.fitness.control_rate, value),
+    },
+    Setting {
+        key: "evolution.min_candidates",
+        env: "MIN_CANDIDATES",
+        flag: "min-candidates",
+        apply: |config, value| set_number(&mut config.worker.min_candidates, value),
+    },
+    Setting {
+        key: "evolution.queue_depth",
+        env: "QUEUE_DEPTH",
+        flag: "queue-depth",
+        apply: |config, value| set_number(&mut config.worker.queue_depth, value),
+    },
+    Setting {
+        key: "evolution.mutation_position",
+        env: "MUTATION_POSITION",
+        flag: "mutation-position",
+        apply: |config, value| set_number(&mut config.worker.mutation.position, value),
+    },
+    Setting {
+        key: "evolution.mutation_radians",
+        env: "MUTATION_RADIANS",
+        flag: "mutation-radians",
+        apply: |config, value| set_number(&mut config.worker.mutation.radians, value),
+    },
+    Setting {
+        key: "evolution.mutation_curviness",
+        env: "MUTATION_CURVINESS",
+        flag: "mutation-curviness",
+        apply: |config, value| set_number(&mut config.worker.mutation.curviness, value),
+    },
+    Setting {
+        key: "evolution.worker_interval",
+        env: "WORKER_INTERVAL",
+        flag: "worker-interval",
+        apply: |config, value| {
+            config.worker.interval = parse_seconds(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "evolution.gc_interval",
+        env: "GC_INTERVAL",
+        flag: "gc-interval",
+        apply: |config, value| {
+            config.worker.gc_interval = match value {
+                "never" => None,
+                _ => Some(parse_seconds(value)?),
+            };
+            Ok(())
+        },
+    },
+    Setting {
+        key: "evolution.test_lifetime",
+        env: "TEST_LIFETIME",
+        flag: "test-lifetime",
+        apply: |config, value| {
+            config.worker.test_lifetime = parse_seconds(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "evolution.expired_tests",
+        env: "EXPIRED_TESTS",
+        flag: "expired-tests",
+        apply: |config, value| set(&mut config.worker.expiry, value),
+    },
+    Setting {
+        key: "evaluator.resolution",
+        env: "EVALUATOR_RESOLUTION",
+        flag: "evaluator-resolution",
+        apply: |config, value| set_number(&mut config.evaluator.resolution, value),
+    },
+    Setting {
+        key: "evaluator.segments",
+        env: "EVALUATOR_SEGMENTS",
+        flag: "evaluator-segments",
+        apply: |config, value| set_number(&mut config.evaluator.segments, value),
+    },
+    Setting {
+        key: "evaluator.distinctness_weight",
+        env: "DISTINCTNESS_WEIGHT",
+        flag: "distinctness-weight",
+        apply: |config, value| set_number(&mut config.evaluator.distinctness_weight, value),
+    },
+    Setting {
+        key: "evaluator.similarity_weight",
+        env: "SIMILARITY_WEIGHT",
+        flag: "similarity-weight",
+        apply: |config, value| set_number(&mut config.evaluator.similarity_weight, value),
+    },
+    Setting {
+        key: "evaluator.contrast_weight",
+        env: "CONTRAST_WEIGHT",
+        flag: "contrast-weight",
+        apply: |config, value| set_number(&mut config.evaluator.contrast_weight, value),
+    },
+    Setting {
+        key: "evaluator.max_generations",
+        env: "MAX_GENERATIONS",
+        flag: "max-generations",
+        apply: |config, value| set_number(&mut config.evaluator.max_generations, value),
+    },
+    Setting {
+        key: "rate_limit.burst",
+        env: "RATE_LIMIT_BURST",
+        flag: "rate-limit-burst",
+        apply: |config, value| set_number(&mut config.rate_limit.burst, value),
+    },
+    Setting {
+        key: "rate_limit.per_second",
+        env: "RATE_LIMIT_PER_SECOND",
+        flag: "rate-limit-per-second",
+        apply: |config, value| set_number(&mut config.rate_limit.per_second, value),
+    },
+    Setting {
+        key: "rate_limit.persist",
+        env: "RATE_LIMIT_PERSIST",
+        flag: "rate-limit-persist",
+        apply: |config, value| set_bool(&mut config.rate_limit.persist, value),
+    },
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1:8080".to_owned(),
+            data_directory: PathBuf::from("DullBananasFontGenData"),
+            storage_mode: storage::Mode::LowSpace,
+            static_directory: PathBuf::from("frontend/static"),
+            index_file: PathBuf::from("index.html"),
+            operators: Vec::new(),
+            metrics_clients: vec![IpAddr::from([127, 0, 0, 1]), IpAddr::from(Ipv6Addr::LOCALHOST)],
+            passwords: user::HashParams::default(),
+            fitness: fitness::Config::default(),
+            evaluator: evaluator::Config::default(),
+            worker: worker::Config::default(),
+            rate_limit: rate_limit::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, then environment variables, then the flags at the start of `args`, and validates the result. Returns the arguments after the flags.
+    pub fn load(args: &[String]) -> Result<(Self, &[String]), InitError> {
+        let (flags, rest) = split_flags(args)?;
+        let mut config = Config::default();
+
+        let file = flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| flag == "config")
+            .map(|(_, path)| path.clone())
+            .or_else(|| std::env::var("CONFIG").ok());
+        match file {
+            Some(path) => config.apply_file(Path::new(&path))?,
+            None if Path::new(DEFAULT_FILE).exists() => config.apply_file(Path::new(DEFAULT_FILE))?,
+            None => {},
+        }
+
+        for setting in SETTINGS {
+            if let Ok(value) = std::env::var(setting.env) {
+                (setting.apply)(&mut config, &value)
+                    .map_err(|error| InitError::Config(format!("{}: {}", setting.env, error)))?;
+            }
+        }
+
+        for (flag, value) in flags.iter().filter(|(flag, _)| flag != "config") {
+            let setting = SETTINGS
+                .iter()
+                .find(|setting| setting.flag == flag)
+                .ok_or_else(|| InitError::Config(format!("unknown flag --{}", flag)))?;
+            (setting.apply)(&mut config, value)
+                .map_err(|error| InitError::Config(format!("--{}: {}", flag, error)))?;
+        }
+
+        config.validate().map_err(InitError::Config)?;
+        Ok((config, rest))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.passwords.validate()?;
+        self.fitness.validate()?;
+        self.evaluator.validate()?;
+        self.worker.validate()?;
+        self.rate_limit.validate()
+    }
+
+    /// The file has a table for each part of a setting's key before the dot. Values can be strings, numbers or booleans, and are parsed like environment variables.
+    fn apply_file(&mut self, path: &Path) -> Result<(), InitError> {
+        let error = |message: String| InitError::Config(format!("{}: {}", path.display(), message));
+        let text = std::fs::read_to_string(path).map_err(|io_error| error(io_error.to_string()))?;
+        let tables: toml::value::Table = toml::from_str(&text).map_err(|toml_error| error(toml_error.to_string()))?;
+
+        for (table_name, table) in tables {
+            let table = match table {
+                toml::Value::Table(table) => table,
+                _ => return Err(error(format!("\"{}\" must be a table", table_name))),
+            };
+            for (name, value) in table {
+                let key = format!("{}.{}", table_name, name);
+                let setting = SETTINGS
+                    .iter()
+                    .find(|setting| setting.key == key)
+                    .ok_or_else(|| error(format!("unknown setting {}", key)))?;
+                let value = match value {
+                    toml::Value::String(string) => string,
+                    toml::Value::Integer(number) => number.to_string(),
+                    toml::Value::Float(number) => number.to_string(),
+                    toml::Value::Boolean(boolean) => boolean.to_string(),
+                    _ => return Err(error(format!("{} must be a string, a number or a boolean", key))),
+                };
+                (setting.apply)(self, &value).map_err(|message| error(format!("{}: {}", key, message)))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A flag's name without the dashes, and its value
+type Flag = (String, String);
+
+/// Splits `--flag value` and `--flag=value` pairs from the start of `args`, until an argument that isn't a flag.
+fn split_flags(args: &[String]) -> Result<(Vec<Flag>, &[String]), InitError> {
+    let mut flags = Vec::new();
+    let mut rest = args;
+    while let Some(flag) = rest.first().and_then(|arg| arg.strip_prefix("--")) {
+        match flag.split_once('=') {
+            Some((flag, value)) => {
+                flags.push((flag.to_owned(), value.to_owned()));
+                rest = &rest[1..];
+            },
+            None => {
+                let value = rest.get(1).ok_or_else(|| InitError::Config(format!("--{} needs a value", flag)))?;
+                flags.push((flag.to_owned(), value.clone()));
+                rest = &rest[2..];
+            },
+        }
+    }
+    Ok((flags, rest))
+}
+
+/// For types whose errors describe the value
+fn set<T: FromStr<Err = String>>(field: &mut T, value: &str) -> Result<(), String> {
+    *field = value.parse()?;
+    Ok(())
+}
+
+fn set_number<T: FromStr>(field: &mut T, value: &str) -> Result<(), String> {
+    *field = value.parse().map_err(|_| format!("invalid number \"{}\"", value))?;
+    Ok(())
+}
+
+fn set_bool(field: &mut bool, value: &str) -> Result<(), String> {
+    *field = value.parse().map_err(|_| format!("expected true or false, not \"{}\"", value))?;
+    Ok(())
+}
+
+fn set_string(field: &mut String, value: &str) -> Result<(), String> {
+    *field = value.to_owned();
+    Ok(())
+}
+
+fn set_list(field: &mut Vec<String>, value: &str) -> Result<(), String> {
+    *field = split_list(value).map(str::to_owned).collect();
+    Ok(())
+}
+
+fn set_addresses(field: &mut Vec<IpAddr>, value: &str) -> Result<(), String> {
+    *field = split_list(value)
+        .map(|address| address.parse().map_err(|_| format!("invalid IP address \"{}\"", address)))
+        .collect::<Result<_, _>>()?;
+    Ok(())
+}
+
+/// Items are separated by commas, and spaces around them are ignored
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn set_path(field: &mut PathBuf, value: &str) -> Result<(), String> {
+    *field = PathBuf::from(value);
+    Ok(())
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()) {
+        Some(duration) if duration <= MAX_DURATION => Ok(duration),
+        _ => Err(format!("invalid number of seconds \"{}\", which must be at most a year", value)),
+    }
+}
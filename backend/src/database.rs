@@ -1,55 +1,179 @@
 // Functions are async for future-proofing
 
 use async_std::stream::{self, Stream};
+use crate::backup;
 use crate::error::{InitError, Error as E};
+use crate::storage::{self, Entries, RawTree, RawView, Storage};
 use deku::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use shared::util::{DekuRW};
+use std::cell::{RefCell};
+use std::collections::{BTreeMap};
+use std::fmt::{self, Display, Formatter};
 use std::marker::{PhantomData};
+use std::path::{Path};
+use std::rc::{Rc};
+use std::str::{FromStr};
+use std::sync::{Arc};
 
 /// Manages all stored data.
 #[derive(Clone)]
 pub struct Database {
-    db: sled::Db,
+    storage: Arc<dyn Storage>,
 }
 
 /// Stores items similarly to `std::collections::BTreeMap<Key, T>`.
 pub struct Tree<T, Key = Id<T>> {
-    db: sled::Db,
-    tree: sled::Tree,
+    storage: Arc<dyn Storage>,
+    tree: Arc<dyn RawTree>,
+    /// Updated with every change to the tree
+    indexes: Vec<Arc<IndexTree<T>>>,
     phantom: PhantomData<(T, Key)>,
 }
 
+/// A `Tree` inside a transaction. Items can only be accessed by key.
+pub struct TransactionalTree<T, Key = Id<T>> {
+    tree: Rc<dyn RawView>,
+    indexes: Vec<IndexView<T>>,
+    phantom: PhantomData<(T, Key)>,
+}
+
+/// Finds the items of a `Tree` by `IndexKey` without scanning the whole tree. Made with `Tree::add_index`.
+pub struct Index<T, Key, IndexKey> {
+    tree: Arc<dyn RawTree>,
+    index: Arc<IndexTree<T>>,
+    phantom: PhantomData<(T, Key, IndexKey)>,
+}
+
+/// The part of an `Index` that its `Tree` keeps up to date. Each entry's key is made by `index_entry`, and its value is empty. Entries of a unique index leave the item's key out of the entry's key, and store it as the value instead, so they can be found in transactions.
+struct IndexTree<T> {
+    tree: Arc<dyn RawTree>,
+    /// Returns the encoded index keys of an item
+    index_keys: Box<IndexKeys<T>>,
+    unique: bool,
+}
+
+type IndexKeys<T> = dyn Fn(&T) -> Result<Vec<Vec<u8>>, E> + Send + Sync;
+
+/// An `IndexTree` inside a transaction
+struct IndexView<T> {
+    index: Arc<IndexTree<T>>,
+    view: Rc<dyn RawView>,
+}
+
+/// An item from `Tree::iter_checked` whose key or value can't be decoded
+pub struct Undecodable {
+    /// The key as it's stored
+    pub key: Vec<u8>,
+    pub error: E,
+}
+
+/// Returned from a transaction's closure to cancel the transaction. Errors from `TransactionalTree` must be passed on with `?`, because `Conflict` makes the closure run again.
+pub enum TransactionError {
+    Abort(E),
+    Conflict,
+}
+
+/// A tuple of `&Tree`s that can be used together in `Database::transaction`.
+pub trait Trees {
+    /// The same tuple with each `Tree` replaced with a `TransactionalTree`
+    type View;
+
+    /// Includes the trees of indexes
+    fn raw_trees(&self) -> Vec<&dyn RawTree>;
+
+    /// `trees` has a view of each tree from `raw_trees`, in the same order.
+    fn view(&self, trees: &[Rc<dyn RawView>]) -> Self::View;
+}
+
 #[derive(DekuRead, DekuWrite)]
-#[deku(endian = "big", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
 pub struct Id<T> {
     id: u64,
     #[deku(skip)]
     phantom: PhantomData<T>,
 }
 
-shared::impl_clone!(Tree<T, Key> { db, tree });
+shared::impl_clone!(Tree<T, Key> { storage, tree, indexes });
+shared::impl_clone!(Index<T, Key, IndexKey> { tree, index });
 shared::impl_clone!(Id<T> { id });
 impl<T> Copy for Id<T> {}
 
 impl Database {
-    /// Open the DullBananasFontGenData directory, which contains all trees.
-    pub async fn open() -> Result<Self, InitError> {
+    /// Open the directory with sled, which contains all trees.
+    pub async fn open(path: impl AsRef<Path>, mode: storage::Mode) -> Result<Self, InitError> {
         Ok(Database {
-            db: sled::Config::default()
-                .path("DullBananasFontGenData")
-                .mode(sled::Mode::LowSpace)
-                .open()?,
+            storage: Arc::new(storage::Sled::open(path.as_ref(), mode)?),
         })
     }
 
+    /// An empty database that is only kept in memory, for tests.
+    pub fn in_memory() -> Self {
+        Database {
+            storage: Arc::new(storage::Memory::default()),
+        }
+    }
+
     /// Open the named tree. `T` must be specified.
     pub async fn tree<T, Key>(&self, name: &'static [u8]) -> Result<Tree<T, Key>, InitError> {
         Ok(Tree {
-            db: self.db.clone(),
-            tree: self.db.open_tree(name)?,
+            storage: self.storage.clone(),
+            tree: self.storage.open_tree(name)?,
+            indexes: Vec::new(),
             phantom: PhantomData,
         })
     }
+
+    /// Runs `f` with each tree as a `TransactionalTree`, so either all of its changes are saved or none of them are. `f` runs again if another transaction changes the same items at the same time, so it shouldn't do anything other than use the trees.
+    pub async fn transaction<Ts, R, F>(&self, trees: Ts, f: F) -> Result<R, E>
+    where
+        Ts: Trees,
+        F: Fn(Ts::View) -> Result<R, TransactionError>,
+    {
+        let result = RefCell::new(None);
+        self.storage.transaction(&trees.raw_trees(), &|views| {
+            *result.borrow_mut() = Some(f(trees.view(views))?);
+            Ok(())
+        })?;
+        Ok(result.into_inner().expect("result of successful transaction"))
+    }
+
+    /// Copies every tree, including index trees, into an archive that `restore` can read. The server can keep running, but writes wait until the trees are copied.
+    pub async fn backup(&self) -> Result<Vec<u8>, E> {
+        backup::write(&self.storage.snapshot()?)
+    }
+
+    /// Returns the name and number of items of each tree, including index trees.
+    pub async fn tree_sizes(&self) -> Result<Vec<(String, usize)>, E> {
+        Ok(self.storage
+            .tree_sizes()?
+            .into_iter()
+            .map(|(name, len)| (String::from_utf8_lossy(&name).into_owned(), len))
+            .collect())
+    }
+
+    /// Writes the trees from an archive made by `backup`. The database must be empty, so nothing is overwritten.
+    pub async fn restore(&self, archive: &[u8]) -> Result<backup::Summary, E> {
+        let (snapshot, summary) = backup::read(archive)?;
+        let is_empty = self.storage
+            .snapshot()?
+            .trees
+            .iter()
+            .all(|(_, items)| items.is_empty());
+        if !is_empty {
+            return Err(E::database_not_empty());
+        }
+
+        for (name, items) in &snapshot.trees {
+            let tree = self.storage.open_tree(name)?;
+            for (key, value) in items {
+                tree.insert(key, value)?;
+            }
+        }
+        self.storage.reserve_ids(snapshot.next_id)?;
+
+        Ok(summary)
+    }
 }
 
 impl<T, Key> Tree<T, Key>
@@ -59,16 +183,17 @@ where
 {
     /// Insert a value with the specified key.
     pub async fn insert_with_key(&self, key: Key, value: &T) -> Result<(), E> {
-        self.tree.insert(
-            key.to_bytes()?,
-            value.to_bytes()?,
-        )?;
-        Ok(())
+        let key = key.to_bytes()?;
+        if self.indexes.is_empty() {
+            self.tree.insert(&key, &value.to_bytes()?)
+        } else {
+            self.indexed_write(&key, Some(value)).map(drop)
+        }
     }
 
     /// Return the item's value, or `Ok(None)` if it doesn't exist.
     pub async fn get_option(&self, key: Key) -> Result<Option<T>, E> {
-        Ok(match self.tree.get(key.to_bytes()?)? {
+        Ok(match self.tree.get(&key.to_bytes()?)? {
             Some(bytes) => Some(DekuRW::read(&bytes)?),
             None => None,
         })
@@ -80,7 +205,13 @@ where
     }
 
     pub async fn remove(&self, key: Key) -> Result<Option<T>, E> {
-        Ok(match self.tree.remove(key.to_bytes()?)? {
+        let key = key.to_bytes()?;
+        let old = if self.indexes.is_empty() {
+            self.tree.remove(&key)?
+        } else {
+            self.indexed_write(&key, None)?
+        };
+        Ok(match old {
             Some(bytes) => Some(DekuRW::read(&bytes)?),
             None => None,
         })
@@ -91,18 +222,406 @@ where
     where
         P: DekuRW,
     {
-        Ok(stream::from_iter(self.tree.scan_prefix(prefix.to_bytes()?)
-            .map(|result| match result {
-                Ok((key, value)) => Ok((
-                    DekuRW::read(&key)?,
+        Ok(read_entries(self.tree.scan_prefix(&prefix.to_bytes()?)))
+    }
+
+    /// Return all items, ordered by key.
+    pub fn iter(&self) -> impl Stream<Item = Result<(Key, T), E>> {
+        read_entries(self.tree.scan_prefix(&[]))
+    }
+
+    /// Return all keys in order, without reading the values.
+    pub fn keys(&self) -> impl Stream<Item = Result<Key, E>> {
+        stream::from_iter(self.tree.scan_prefix(&[])
+            .map(|result| Ok(DekuRW::read(&result?.0)?))
+        )
+    }
+
+    /// Like `iter`, but an item that can't be decoded is returned as `Undecodable` instead of an error.
+    pub fn iter_checked(&self) -> impl Stream<Item = Result<Result<(Key, T), Undecodable>, E>> {
+        stream::from_iter(self.tree.scan_prefix(&[])
+            .map(|result| {
+                let (key, value) = result?;
+                Ok(match (DekuRW::read(&key), DekuRW::read(&value)) {
+                    (Ok(key), Ok(value)) => Ok((key, value)),
+                    (Err(error), _) | (_, Err(error)) => Err(Undecodable {
+                        key,
+                        error: error.into(),
+                    }),
+                })
+            })
+        )
+    }
+
+    /// Removes an item from `iter_checked` that can't be decoded. Its index entries can't be found, so they're left for `check_indexes` to remove.
+    pub async fn remove_undecodable(&self, key: &[u8]) -> Result<(), E> {
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    /// Returns the number of index entries that are missing or don't belong to an item, and fixes them if `repair` is true.
+    pub async fn check_indexes(&self, repair: bool) -> Result<u64, E> {
+        let mut wrong = 0;
+        for index in &self.indexes {
+            let mut expected = BTreeMap::new();
+            for result in self.tree.scan_prefix(&[]) {
+                let (key, value) = result?;
+                // Items that can't be decoded have no entries
+                if let Ok(item) = DekuRW::read(&value) {
+                    for index_key in (index.index_keys)(&item)? {
+                        let (entry, entry_value) = index.entry(&index_key, &key);
+                        expected.insert(entry, entry_value);
+                    }
+                }
+            }
+
+            for result in index.tree.scan_prefix(&[]) {
+                let (entry, value) = result?;
+                if expected.get(&entry) == Some(&value) {
+                    expected.remove(&entry);
+                } else {
+                    wrong += 1;
+                    if repair {
+                        index.tree.remove(&entry)?;
+                    }
+                }
+            }
+            wrong += expected.len() as u64;
+            if repair {
+                for (entry, value) in expected {
+                    index.tree.insert(&entry, &value)?;
+                }
+            }
+        }
+        Ok(wrong)
+    }
+
+    /// Adds an index that is kept up to date by every later change to the tree, including changes in transactions. Clones of the tree made before this don't update the index. `index_keys` returns every key that finds the item, which can be none.
+    ///
+    /// If the index is empty, it's built from the tree's items, so indexes can be added to trees that already have items.
+    pub async fn add_index<IndexKey>(
+        &mut self,
+        name: &'static [u8],
+        index_keys: fn(&T) -> Vec<IndexKey>,
+    ) -> Result<Index<T, Key, IndexKey>, InitError>
+    where
+        T: 'static,
+        IndexKey: DekuRW + 'static,
+    {
+        self.add_index_tree(name, index_keys, false)
+    }
+
+    /// Like `add_index`, but no two items can have the same index key, and `TransactionalTree::get_unique` can find items with the index. An index that was stored before it was unique is rebuilt.
+    pub async fn add_unique_index<IndexKey>(
+        &mut self,
+        name: &'static [u8],
+        index_keys: fn(&T) -> Vec<IndexKey>,
+    ) -> Result<Index<T, Key, IndexKey>, InitError>
+    where
+        T: 'static,
+        IndexKey: DekuRW + 'static,
+    {
+        self.add_index_tree(name, index_keys, true)
+    }
+
+    fn add_index_tree<IndexKey>(
+        &mut self,
+        name: &'static [u8],
+        index_keys: fn(&T) -> Vec<IndexKey>,
+        unique: bool,
+    ) -> Result<Index<T, Key, IndexKey>, InitError>
+    where
+        T: 'static,
+        IndexKey: DekuRW + 'static,
+    {
+        let index = IndexTree {
+            tree: self.storage.open_tree(name)?,
+            index_keys: Box::new(move |item| index_keys(item)
+                .into_iter()
+                .map(|index_key| Ok(index_key.to_bytes()?))
+                .collect()
+            ),
+            unique,
+        };
+
+        // Only entries of unique indexes have values
+        let is_current = match index.tree.scan_prefix(&[]).next().transpose()? {
+            Some((_, value)) => value.is_empty() != unique,
+            None => false,
+        };
+        if !is_current {
+            for result in index.tree.scan_prefix(&[]) {
+                index.tree.remove(&result?.0)?;
+            }
+            for result in self.tree.scan_prefix(&[]) {
+                let (key, value) = result?;
+                for index_key in (index.index_keys)(&DekuRW::read(&value).map_err(E::from)?)? {
+                    let (entry, entry_value) = index.entry(&index_key, &key);
+                    index.tree.insert(&entry, &entry_value)?;
+                }
+            }
+        }
+
+        let index = Arc::new(index);
+        self.indexes.push(index.clone());
+        Ok(Index {
+            tree: self.tree.clone(),
+            index,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Changes the item and its index entries in one transaction. Returns the old value.
+    fn indexed_write(&self, key: &[u8], value: Option<&T>) -> Result<Option<Vec<u8>>, E> {
+        let mut trees = Vec::new();
+        self.push_raw_trees(&mut trees);
+        let old = RefCell::new(None);
+        self.storage.transaction(&trees, &|views| {
+            *old.borrow_mut() = self.view(&mut views.iter()).write(key, value)?;
+            Ok(())
+        })?;
+        Ok(old.into_inner())
+    }
+}
+
+impl<T, Key> Tree<T, Key> {
+    fn push_raw_trees<'a>(&'a self, trees: &mut Vec<&'a dyn RawTree>) {
+        trees.push(&*self.tree);
+        trees.extend(self.indexes.iter().map(|index| &*index.tree));
+    }
+
+    /// Takes the views of the trees from `push_raw_trees`.
+    fn view(&self, views: &mut std::slice::Iter<Rc<dyn RawView>>) -> TransactionalTree<T, Key> {
+        let mut next = || views.next().expect("view for each tree").clone();
+        let tree = next();
+        let indexes = self.indexes
+            .iter()
+            .map(|index| IndexView {
+                index: index.clone(),
+                view: next(),
+            })
+            .collect();
+        TransactionalTree {
+            tree,
+            indexes,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T, Key, IndexKey> Index<T, Key, IndexKey>
+where
+    T: DekuRW,
+    Key: DekuRW,
+    IndexKey: DekuRW,
+{
+    /// Return the items that the index key finds, ordered by key.
+    pub fn get(&self, index_key: IndexKey) -> Result<impl Stream<Item = Result<(Key, T), E>>, E> {
+        let prefix = index_entry(&index_key.to_bytes()?, &[]);
+        let tree = self.tree.clone();
+        let unique = self.index.unique;
+        Ok(stream::from_iter(self.index.tree
+            .scan_prefix(&prefix)
+            .map(move |result| {
+                let (entry, entry_value) = result?;
+                let key = if unique { &entry_value[..] } else { &entry[prefix.len()..] };
+                let value = tree.get(key)?.ok_or_else(E::expect_db_item::<T>)?;
+                Ok((
+                    DekuRW::read(key)?,
                     DekuRW::read(&value)?,
-                )),
-                Err(err) => Err(err.into()),
+                ))
             })
         ))
     }
 }
 
+impl<T> IndexTree<T> {
+    /// The key and value of the entry that finds the item with `key`
+    fn entry(&self, index_key: &[u8], key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        if self.unique {
+            (index_entry(index_key, &[]), key.to_vec())
+        } else {
+            (index_entry(index_key, key), Vec::new())
+        }
+    }
+}
+
+/// The index key's length comes first, so index keys that begin with other index keys don't find the same items.
+fn index_entry(index_key: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(4 + index_key.len() + key.len());
+    entry.extend_from_slice(&(index_key.len() as u32).to_be_bytes());
+    entry.extend_from_slice(index_key);
+    entry.extend_from_slice(key);
+    entry
+}
+
+fn read_entries<T, Key>(iter: Entries) -> impl Stream<Item = Result<(Key, T), E>>
+where
+    T: DekuRW,
+    Key: DekuRW,
+{
+    stream::from_iter(iter
+        .map(|result| {
+            let (key, value) = result?;
+            Ok((
+                DekuRW::read(&key)?,
+                DekuRW::read(&value)?,
+            ))
+        })
+    )
+}
+
+impl<T, Key> TransactionalTree<T, Key>
+where
+    T: DekuRW,
+    Key: DekuRW,
+{
+    pub fn insert_with_key(&self, key: Key, value: &T) -> Result<(), TransactionError> {
+        self.write(&key.to_bytes()?, Some(value)).map(drop)
+    }
+
+    pub fn get_option(&self, key: Key) -> Result<Option<T>, TransactionError> {
+        Ok(match self.tree.get(&key.to_bytes()?)? {
+            Some(bytes) => Some(DekuRW::read(&bytes)?),
+            None => None,
+        })
+    }
+
+    pub fn get(&self, key: Key) -> Result<T, TransactionError> {
+        Ok(self.get_option(key)?.ok_or_else(|| E::expect_db_item::<T>())?)
+    }
+
+    pub fn remove(&self, key: Key) -> Result<Option<T>, TransactionError> {
+        Ok(match self.write(&key.to_bytes()?, None)? {
+            Some(bytes) => Some(DekuRW::read(&bytes)?),
+            None => None,
+        })
+    }
+
+    /// Inserts the value, or removes the item if it's `None`, and updates the indexes. Returns the old value if there are indexes or it was removed.
+    fn write(&self, key: &[u8], value: Option<&T>) -> Result<Option<Vec<u8>>, TransactionError> {
+        let old = match value {
+            Some(value) if self.indexes.is_empty() => {
+                self.tree.insert(key, &value.to_bytes()?)?;
+                return Ok(None);
+            },
+            Some(value) => {
+                let old = self.tree.get(key)?;
+                self.tree.insert(key, &value.to_bytes()?)?;
+                old
+            },
+            None => self.tree.remove(key)?,
+        };
+
+        let old_item: Option<T> = match (&old, self.indexes.is_empty()) {
+            (Some(old), false) => Some(DekuRW::read(old)?),
+            _ => None,
+        };
+        for IndexView { index, view } in &self.indexes {
+            if let Some(old_item) = &old_item {
+                for index_key in (index.index_keys)(old_item)? {
+                    view.remove(&index.entry(&index_key, key).0)?;
+                }
+            }
+            if let Some(value) = value {
+                for index_key in (index.index_keys)(value)? {
+                    let (entry, entry_value) = index.entry(&index_key, key);
+                    if index.unique && view.get(&entry)?.is_some_and(|other| other != key) {
+                        return Err(E::duplicate_index_key().into());
+                    }
+                    view.insert(&entry, &entry_value)?;
+                }
+            }
+        }
+
+        Ok(old)
+    }
+}
+
+impl<T, Key> TransactionalTree<T, Key>
+where
+    T: DekuRW,
+    Key: DekuRW,
+{
+    /// Finds the item with the index key, like `Index::get`. The index must be one of this tree's unique indexes.
+    pub fn get_unique<IndexKey: DekuRW>(&self, index: &Index<T, Key, IndexKey>, index_key: IndexKey) -> Result<Option<(Key, T)>, TransactionError> {
+        let IndexView { view, .. } = self.indexes
+            .iter()
+            .find(|view| view.index.unique && Arc::ptr_eq(&view.index, &index.index))
+            .expect("unique index of the tree");
+        let key = match view.get(&index_entry(&index_key.to_bytes()?, &[]))? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let value = self.tree.get(&key)?.ok_or_else(E::expect_db_item::<T>)?;
+        Ok(Some((DekuRW::read(&key)?, DekuRW::read(&value)?)))
+    }
+}
+
+impl<T> TransactionalTree<T, Id<T>>
+where
+    T: DekuRW,
+{
+    /// Like `Tree::insert`. The key isn't reused if the transaction runs again.
+    pub fn insert(&self, value: &T) -> Result<Id<T>, TransactionError> {
+        let key = self.generate_id()?;
+        self.insert_with_key(key, value)?;
+        Ok(key)
+    }
+
+    /// Like `Id::generate`
+    pub fn generate_id(&self) -> Result<Id<T>, TransactionError> {
+        Ok(Id {
+            id: self.tree.generate_id()?,
+            phantom: PhantomData,
+        })
+    }
+}
+
+macro_rules! impl_trees {
+    ($($T:ident $Key:ident $index:tt),+) => {
+        impl<'a, $($T, $Key),+> Trees for ($(&'a Tree<$T, $Key>,)+) {
+            type View = ($(TransactionalTree<$T, $Key>,)+);
+
+            fn raw_trees(&self) -> Vec<&dyn RawTree> {
+                let mut trees = Vec::new();
+                $(self.$index.push_raw_trees(&mut trees);)+
+                trees
+            }
+
+            fn view(&self, trees: &[Rc<dyn RawView>]) -> Self::View {
+                let mut trees = trees.iter();
+                ($(self.$index.view(&mut trees),)+)
+            }
+        }
+    };
+}
+
+impl_trees!(T0 K0 0);
+impl_trees!(T0 K0 0, T1 K1 1);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4, T5 K5 5);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4, T5 K5 5, T6 K6 6);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4, T5 K5 5, T6 K6 6, T7 K7 7);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4, T5 K5 5, T6 K6 6, T7 K7 7, T8 K8 8);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4, T5 K5 5, T6 K6 6, T7 K7 7, T8 K8 8, T9 K9 9);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4, T5 K5 5, T6 K6 6, T7 K7 7, T8 K8 8, T9 K9 9, T10 K10 10);
+impl_trees!(T0 K0 0, T1 K1 1, T2 K2 2, T3 K3 3, T4 K4 4, T5 K5 5, T6 K6 6, T7 K7 7, T8 K8 8, T9 K9 9, T10 K10 10, T11 K11 11);
+
+impl From<E> for TransactionError {
+    fn from(error: E) -> Self {
+        TransactionError::Abort(error)
+    }
+}
+
+impl From<deku::DekuError> for TransactionError {
+    fn from(error: deku::DekuError) -> Self {
+        E::from(error).into()
+    }
+}
+
 impl<T> Tree<T, Id<T>>
 where
     T: DekuRW,
@@ -110,7 +629,7 @@ where
     /// Insert a value with an automatically chosen key that hasn't been used yet, and return the key.
     pub async fn insert(&self, value: &T) -> Result<Id<T>, E> {
         let key = Id {
-            id: self.db.generate_id()?,
+            id: self.storage.generate_id()?,
             phantom: PhantomData,
         };
         self.insert_with_key(key, value).await?;
@@ -138,7 +657,73 @@ impl<T> Id<T> {
     /// Reserve an `Id` for any type. In most cases, use `Tree::insert` instead.
     pub async fn generate(tree: &Tree<T, Self>) -> Result<Self, E> {
         Ok(Id {
-            id: tree.db.generate_id()?,
+            id: tree.storage.generate_id()?,
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<T> PartialEq for Id<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Id<T> {}
+
+impl<T> PartialOrd for Id<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Id<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl<T> From<Id<T>> for u64 {
+    fn from(id: Id<T>) -> Self {
+        id.id
+    }
+}
+
+// `Id`s appear in URLs and JSON as plain numbers
+
+impl<T> fmt::Debug for Id<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.id, f)
+    }
+}
+
+impl<T> Display for Id<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.id.fmt(f)
+    }
+}
+
+impl<T> FromStr for Id<T> {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        Ok(Id {
+            id: string.parse()?,
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<T> Serialize for Id<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Id<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Id {
+            id: u64::deserialize(deserializer)?,
             phantom: PhantomData,
         })
     }
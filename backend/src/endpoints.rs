@@ -1,14 +1,464 @@
+use crate::active_test::{Keystroke, TestKind};
+use crate::config::{Config};
+use crate::database::{Id};
 use crate::state::{State};
-use crate::error::{InitError};
+use crate::error::{InitError, Error as E};
+use crate::font;
+use crate::rate_limit::{Key, Limiter};
+use crate::user::{User};
+use crate::worker;
+use serde::{Deserialize, Serialize};
+use shared::error::{ErrorCode, ErrorResponse};
+use shared::event::{FontEvent};
+use shared::glyph::{Glyph};
+use shared::presentation::{Presentation};
+use shared::util::{DekuRW};
+use std::net::{IpAddr, SocketAddr};
+use std::str::{FromStr};
+use std::sync::{Arc};
+use std::time::{Duration, Instant};
+use tide::{Body, Response, StatusCode};
+use tide::http::{Mime};
 
 type Request = tide::Request<State>;
 
-pub fn init(server: &mut tide::Server<State>) -> Result<(), InitError> {
-    server.at("/style.css").serve_file("frontend/static/style.css")?;
-    server.at("/target/wasm.js").serve_file("frontend/static/target/wasm.js")?;
-    server.at("/target/wasm_bg.wasm").serve_file("frontend/static/target/wasm_bg.wasm")?;
-    server.at("/").serve_file("index.html")?;
-    server.at("/*").serve_file("index.html")?;
+const USER_ID: &str = "user_id";
+/// Leaderboard entries returned without a `limit`
+const LEADERBOARD_LIMIT: usize = 100;
+/// The largest `limit` of a leaderboard
+const MAX_LEADERBOARD_LIMIT: usize = 1000;
+/// Time between keep-alive messages on event streams, which also find clients that have left
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
 
+pub fn init(server: &mut tide::Server<State>, config: &Config, limiter: Limiter) -> Result<(), InitError> {
+    let mut session_secret = [0; 32];
+    getrandom::getrandom(&mut session_secret)?;
+    server.with(tide::sessions::SessionMiddleware::new(
+        tide::sessions::MemoryStore::new(),
+        &session_secret,
+    ));
+    server.with(tide::utils::After(error_response));
+    let operators = Operators(Arc::new(config.operators.clone()));
+
+    api(server, "/api/users").post(register);
+    api(server, "/api/users/:user/stats").get(user_stats);
+    api(server, "/api/leaderboard").get(leaderboard);
+    api(server, "/api/session").post(log_in).delete(log_out);
+    api(server, "/api/fonts").post(add_font);
+    api(server, "/api/fonts/:font/test").with(limiter.clone()).post(add_next_test);
+    api(server, "/api/fonts/:font/test-kind").with(operators.clone()).put(set_test_kind);
+    api(server, "/api/fonts/:font/evolve").with(operators.clone()).post(evolve_offline);
+    api(server, "/api/fonts/:font/history").get(font_history);
+    api(server, "/api/fonts/:font/current-version").with(operators.clone()).put(roll_back_font);
+    api(server, "/api/fonts/:font/events").get(font_events);
+    api(server, "/api/fonts/:font/leaderboard").get(font_leaderboard);
+    api(server, "/api/glyphs/:glyph/ancestry").get(glyph_ancestry);
+    api(server, "/api/worker").get(get_worker);
+    api(server, "/api/worker").with(operators.clone()).put(set_worker);
+    api(server, "/api/gc").with(operators.clone()).post(collect_garbage);
+    api(server, "/api/backup").with(operators).get(backup);
+    api(server, "/api/test").get(get_test);
+    api(server, "/api/test/keystroke").with(limiter.clone()).post(submit_keystroke);
+    api(server, "/api/test/choice").with(limiter).post(submit_choice);
+    api(server, "/api/font-versions/:version/fork").post(fork_font);
+    api(server, "/api/font-versions/:version/diff/:new_version").get(version_diff);
+    api(server, "/api/font-versions/:version/confusions.json").get(confusion_matrix_json);
+    api(server, "/api/font-versions/:version/confusions.csv").get(confusion_matrix_csv);
+    server.at("/metrics").with(MetricsClients(Arc::new(config.metrics_clients.clone()))).get(metrics);
+
+    crate::assets::init(server, config)?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct Login {
+    name: String,
+    password: String,
+}
+
+/// Also used for forking a font
+#[derive(Deserialize)]
+struct AddFontQuery {
+    test_kind: Option<TestKind>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    index: usize,
+    /// Milliseconds between showing the glyphs and choosing one
+    time: f64,
+}
+
+#[derive(Deserialize)]
+struct Evolve {
+    generations: u32,
+}
+
+#[derive(Serialize)]
+struct CurrentVersion {
+    current_version: Id<font::Version>,
+}
+
+/// The evolution worker's settings that can be changed while the server runs
+#[derive(Deserialize, Serialize)]
+struct WorkerStatus {
+    paused: bool,
+    interval_millis: u64,
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    limit: Option<usize>,
+}
+
+impl LeaderboardQuery {
+    fn limit(&self) -> Result<usize, E> {
+        match self.limit {
+            Some(limit) if limit > MAX_LEADERBOARD_LIMIT => Err(E::invalid_request(&format!("the limit must be at most {}", MAX_LEADERBOARD_LIMIT))),
+            limit => Ok(limit.unwrap_or(LEADERBOARD_LIMIT)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GcQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+#[serde(bound = "")]
+struct Created<T> {
+    id: Id<T>,
+}
+
+fn user_id(req: &Request) -> Result<Id<User>, E> {
+    req.session().get(USER_ID).ok_or_else(E::not_logged_in)
+}
+
+/// Adds a route whose requests are timed in `Metrics::request_duration`, labeled with `path`
+fn api<'a>(server: &'a mut tide::Server<State>, path: &'static str) -> tide::Route<'a, State> {
+    let mut route = server.at(path);
+    route.with(Timed(path));
+    route
+}
+
+struct Timed(&'static str);
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for Timed {
+    async fn handle(&self, req: Request, next: tide::Next<'_, State>) -> tide::Result {
+        let start = Instant::now();
+        let metrics = req.state().metrics().clone();
+        let method = req.method();
+        let response = next.run(req).await;
+        // `error_response` hasn't set the status of errors yet
+        let status = response.downcast_error::<E>().map_or(response.status(), E::status);
+        metrics.request_duration.observe(
+            &[method.as_ref(), self.0, &u16::from(status).to_string()],
+            start.elapsed().as_secs_f64(),
+        );
+        Ok(response)
+    }
+}
+
+/// Only lets the users named in `Config::operators` through
+#[derive(Clone)]
+struct Operators(Arc<Vec<String>>);
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for Operators {
+    async fn handle(&self, req: Request, next: tide::Next<'_, State>) -> tide::Result {
+        let name = req.state().user_name(user_id(&req)?).await?;
+        if !self.0.contains(&name) {
+            return Err(E::not_operator().into());
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+/// Only lets requests from `Config::metrics_clients` through. The address is the one the connection comes from, like in `Limiter`.
+struct MetricsClients(Arc<Vec<IpAddr>>);
+
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for MetricsClients {
+    async fn handle(&self, req: Request, next: tide::Next<'_, State>) -> tide::Result {
+        let address = req.peer_addr().and_then(|address| address.parse::<SocketAddr>().ok());
+        if !address.is_some_and(|address| self.0.contains(&address.ip())) {
+            return Err(E::address_not_allowed().into());
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+/// Takes a token from the client address's bucket, and the user's bucket if there's a session. The address is the one the connection comes from, because headers such as `Forwarded` could be made up to get a new bucket for every request.
+#[tide::utils::async_trait]
+impl tide::Middleware<State> for Limiter {
+    async fn handle(&self, req: Request, next: tide::Next<'_, State>) -> tide::Result {
+        let mut keys = vec![Key::address(req.peer_addr().unwrap_or("unknown"))];
+        if let Some(user_id) = req.session().get(USER_ID) {
+            keys.push(Key::user(user_id));
+        }
+
+        match self.take(&keys).await? {
+            Ok(()) => Ok(next.run(req).await),
+            Err(retry_after) => {
+                let mut response: Response = tide::Error::from(E::rate_limited(retry_after)).into();
+                // Rounded up, because `Retry-After` only has whole seconds
+                response.insert_header("Retry-After", retry_after.as_secs_f64().ceil().to_string());
+                Ok(response)
+            },
+        }
+    }
+}
+
+/// Parses an ID or other value from the path
+fn param<T: FromStr>(req: &Request, name: &str) -> Result<T, E> {
+    let value = req.param(name).unwrap_or_default();
+    value.parse().map_err(|_| E::invalid_request(&format!("{} \"{}\" can't be parsed", name.replace('_', " "), value)))
+}
+
+/// Gives every error response an `ErrorResponse` body. Errors that don't come from `Error`, such as invalid JSON bodies, keep their status.
+async fn error_response(mut response: Response) -> tide::Result {
+    let (status, body) = if let Some(error) = response.downcast_error::<E>() {
+        (error.status(), error.to_response())
+    } else if let Some(error) = response.error() {
+        let code = if error.status().is_client_error() { ErrorCode::InvalidRequest } else { ErrorCode::Internal };
+        (error.status(), ErrorResponse { code, message: error.to_string() })
+    } else {
+        return Ok(response);
+    };
+    response.set_status(status);
+    response.set_body(Body::from_json(&body)?);
+    Ok(response)
+}
+
+fn test_response(presentation: Option<Presentation>) -> tide::Result {
+    Ok(match presentation {
+        Some(presentation) => Body::from_json(&presentation)?.into(),
+        None => Response::new(StatusCode::NoContent),
+    })
+}
+
+async fn register(mut req: Request) -> tide::Result {
+    let Login { name, password } = req.body_json().await?;
+    let id = req.state().add_user(&name, &password).await?;
+    req.session_mut().insert(USER_ID, id)?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn log_in(mut req: Request) -> tide::Result {
+    let Login { name, password } = req.body_json().await?;
+    let id = req.state().log_in(&name, &password).await?;
+    req.session_mut().insert(USER_ID, id)?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn log_out(mut req: Request) -> tide::Result {
+    req.session_mut().destroy();
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+/// The body contains each glyph prefixed with its length as a big-endian `u32`. The `test_kind` query parameter defaults to `single`.
+async fn add_font(mut req: Request) -> tide::Result {
+    user_id(&req)?;
+    let AddFontQuery { test_kind } = req.query()?;
+    let bytes = req.body_bytes().await?;
+
+    let mut glyphs = Vec::new();
+    let mut rest = &bytes[..];
+    while let Some((len, remaining)) = rest.split_first_chunk::<4>() {
+        let len = std::cmp::min(u32::from_be_bytes(*len) as usize, remaining.len());
+        let (glyph_bytes, remaining) = remaining.split_at(len);
+        let glyph = <Glyph as DekuRW>::read(glyph_bytes)
+            .map_err(|error| E::invalid_request(&format!("glyph {} can't be decoded ({})", glyphs.len(), error)))?;
+        glyphs.push(glyph);
+        rest = remaining;
+    }
+
+    let id = req.state().add_font(glyphs, test_kind.unwrap_or(TestKind::Single)).await?;
+    Ok(Body::from_json(&Created { id })?.into())
+}
+
+/// Responds with no content if the font has no glyphs to test.
+async fn add_next_test(req: Request) -> tide::Result {
+    let user_id = user_id(&req)?;
+    let font_id = param(&req, "font")?;
+    req.state().add_next_test(font_id, user_id).await?;
+    test_response(req.state().get_test(user_id).await?)
+}
+
+async fn set_test_kind(mut req: Request) -> tide::Result {
+    let font_id = param(&req, "font")?;
+    let test_kind: TestKind = req.body_json().await?;
+    req.state().set_test_kind(font_id, test_kind).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+/// Runs generations with the synthetic fitness from `evaluator`, without waiting for testers.
+async fn evolve_offline(mut req: Request) -> tide::Result {
+    let font_id = param(&req, "font")?;
+    let Evolve { generations } = req.body_json().await?;
+    let current_version = req.state().evolve_offline(font_id, generations).await?;
+    Ok(Body::from_json(&CurrentVersion { current_version })?.into())
+}
+
+/// The body is the ID of an earlier version in the font's history.
+async fn roll_back_font(mut req: Request) -> tide::Result {
+    let font_id = param(&req, "font")?;
+    let version_id = req.body_json().await?;
+    let current_version = req.state().roll_back_font(font_id, version_id).await?;
+    Ok(Body::from_json(&CurrentVersion { current_version })?.into())
+}
+
+async fn fork_font(req: Request) -> tide::Result {
+    user_id(&req)?;
+    let version_id = param(&req, "version")?;
+    let AddFontQuery { test_kind } = req.query()?;
+    let id = req.state().fork_font(version_id, test_kind.unwrap_or(TestKind::Single)).await?;
+    Ok(Body::from_json(&Created { id })?.into())
+}
+
+async fn font_history(req: Request) -> tide::Result {
+    let font_id = param(&req, "font")?;
+    let history = req.state().font_history(font_id).await?;
+    Ok(Body::from_json(&history)?.into())
+}
+
+/// Server-sent events for the font, starting with its current version
+async fn font_events(req: Request) -> tide::Result {
+    let font_id = param(&req, "font")?;
+    let (current, events) = req.state().watch_font(font_id).await?;
+    Ok(tide::sse::upgrade(req, move |_req, sender| {
+        let (current, events) = (current.clone(), events.clone());
+        async move {
+            send_event(&sender, &current).await?;
+            loop {
+                match async_std::future::timeout(KEEP_ALIVE, events.recv()).await {
+                    Ok(Ok(event)) => send_event(&sender, &event).await?,
+                    // The subscriber fell behind and was dropped, so the browser reconnects and gets the current version
+                    Ok(Err(_)) => return Ok(()),
+                    // Browsers don't dispatch events without listeners
+                    Err(_) => sender.send("keep_alive", "", None).await?,
+                }
+            }
+        }
+    }))
+}
+
+async fn send_event(sender: &tide::sse::Sender, event: &FontEvent) -> tide::Result<()> {
+    sender.send(event.name(), serde_json::to_string(event)?, None).await?;
     Ok(())
 }
+
+async fn leaderboard(req: Request) -> tide::Result {
+    let query: LeaderboardQuery = req.query()?;
+    let leaderboard = req.state().leaderboard(None, query.limit()?).await?;
+    Ok(Body::from_json(&leaderboard)?.into())
+}
+
+async fn font_leaderboard(req: Request) -> tide::Result {
+    let font_id = param(&req, "font")?;
+    let query: LeaderboardQuery = req.query()?;
+    let leaderboard = req.state().leaderboard(Some(font_id), query.limit()?).await?;
+    Ok(Body::from_json(&leaderboard)?.into())
+}
+
+async fn user_stats(req: Request) -> tide::Result {
+    let user_id = param(&req, "user")?;
+    let stats = req.state().user_stats(user_id).await?;
+    Ok(Body::from_json(&stats)?.into())
+}
+
+async fn glyph_ancestry(req: Request) -> tide::Result {
+    let glyph_id = param(&req, "glyph")?;
+    let ancestry = req.state().glyph_ancestry(glyph_id).await?;
+    Ok(Body::from_json(&ancestry)?.into())
+}
+
+async fn get_worker(req: Request) -> tide::Result {
+    let worker = req.state().worker();
+    Ok(Body::from_json(&WorkerStatus {
+        paused: worker.is_paused(),
+        interval_millis: worker.interval().as_millis() as u64,
+    })?.into())
+}
+
+async fn set_worker(mut req: Request) -> tide::Result {
+    let WorkerStatus { paused, interval_millis } = req.body_json().await?;
+    let interval = Duration::from_millis(interval_millis);
+    if interval < worker::MIN_INTERVAL {
+        return Err(E::invalid_request(&format!("the interval must be at least {} milliseconds", worker::MIN_INTERVAL.as_millis())).into());
+    }
+    let worker = req.state().worker();
+    worker.set_interval(interval);
+    worker.set_paused(paused);
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+/// Deletes unreachable glyphs and versions, or only counts them with `?dry_run=true`.
+async fn collect_garbage(req: Request) -> tide::Result {
+    let GcQuery { dry_run } = req.query()?;
+    let report = req.state().collect_garbage(dry_run).await?;
+    Ok(Body::from_json(&report)?.into())
+}
+
+/// Downloads a copy of the whole database without stopping the server, which the admin tool can `restore`.
+async fn backup(req: Request) -> tide::Result {
+    let archive = req.state().backup().await?;
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(archive);
+    response.set_content_type(tide::http::mime::BYTE_STREAM);
+    response.insert_header("Content-Disposition", "attachment; filename=\"fontgen.backup\"");
+    Ok(response)
+}
+
+async fn get_test(req: Request) -> tide::Result {
+    let user_id = user_id(&req)?;
+    test_response(req.state().get_test(user_id).await?)
+}
+
+async fn submit_keystroke(mut req: Request) -> tide::Result {
+    let user_id = user_id(&req)?;
+    let keystroke: Keystroke = req.body_json().await?;
+    req.state().submit_keystroke(user_id, keystroke).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn submit_choice(mut req: Request) -> tide::Result {
+    let user_id = user_id(&req)?;
+    let Choice { index, time } = req.body_json().await?;
+    req.state().submit_choice(user_id, index, time).await?;
+    Ok(Response::new(StatusCode::NoContent))
+}
+
+async fn version_diff(req: Request) -> tide::Result {
+    let old_version = param(&req, "version")?;
+    let new_version = param(&req, "new_version")?;
+    let diff = req.state().version_diff(old_version, new_version).await?;
+    Ok(Body::from_json(&diff)?.into())
+}
+
+async fn confusion_matrix_json(req: Request) -> tide::Result {
+    let version_id = param(&req, "version")?;
+    let matrix = req.state().confusion_matrix(version_id).await?;
+    Ok(Body::from_json(&matrix)?.into())
+}
+
+async fn confusion_matrix_csv(req: Request) -> tide::Result {
+    let version_id = param(&req, "version")?;
+    let matrix = req.state().confusion_matrix(version_id).await?;
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(matrix.to_csv());
+    response.set_content_type("text/csv; charset=utf-8".parse::<Mime>()?);
+    Ok(response)
+}
+
+/// Counters and histograms in the Prometheus text format
+async fn metrics(req: Request) -> tide::Result {
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(req.state().render_metrics().await?);
+    response.set_content_type("text/plain; version=0.0.4; charset=utf-8".parse::<Mime>()?);
+    Ok(response)
+}
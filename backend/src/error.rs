@@ -1,9 +1,16 @@
+use shared::error::{ErrorCode, ErrorResponse};
 use std::fmt::{self, Display, Formatter}; // includes `Display::fmt` method
 use std::io::{self};
+use std::time::{Duration};
+use tide::{StatusCode};
 
 #[derive(Debug)]
 pub enum InitError {
+    Config(String),
+    /// From reading the database, such as when an index is built
+    Database(Error),
     Io(io::Error),
+    Random(getrandom::Error),
     Sled(sled::Error),
 }
 
@@ -13,6 +20,18 @@ impl From<io::Error> for InitError {
     }
 }
 
+impl From<Error> for InitError {
+    fn from(error: Error) -> Self {
+        InitError::Database(error)
+    }
+}
+
+impl From<getrandom::Error> for InitError {
+    fn from(error: getrandom::Error) -> Self {
+        InitError::Random(error)
+    }
+}
+
 impl From<sled::Error> for InitError {
     fn from(error: sled::Error) -> Self {
         InitError::Sled(error)
@@ -22,49 +41,219 @@ impl From<sled::Error> for InitError {
 impl Display for InitError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
+            InitError::Config(message) => message.fmt(f),
+            InitError::Database(error) => error.fmt(f),
             InitError::Io(error) => match error.kind() {
                 io::ErrorKind::AddrInUse => "address or port is already taken (set the ADDRESS environment variable to change it)".fmt(f),
                 io::ErrorKind::AddrNotAvailable => "address is invalid".fmt(f),
                 _ => error.fmt(f),
             },
+            InitError::Random(error) => error.fmt(f),
             InitError::Sled(error) => error.fmt(f),
         }
     }
 }
 
+/// Each category has its own HTTP status. Categories that clients can cause in more than one way also have a specific code.
 #[derive(Debug)]
-pub struct Error {
-    message: String,
+pub enum Error {
+    /// Something that was requested or referred to doesn't exist
+    NotFound(ErrorCode, String),
+    /// The request needs a session, or the login is wrong
+    Unauthorized(ErrorCode, String),
+    /// The user or client address isn't allowed to make the request
+    Forbidden(ErrorCode, String),
+    /// The request is invalid
+    Validation(ErrorCode, String),
+    /// The request conflicts with the stored data
+    Conflict(ErrorCode, String),
+    /// The user or client address made too many requests
+    RateLimited(String),
+    /// The database, file system or another part of the server failed
+    Storage(String),
+    /// Stored data couldn't be decoded or encoded
+    Decode(String),
 }
 
 impl Error {
     pub fn expect_db_item<T>() -> Self {
-        Error {
-            message: format!(
+        Error::NotFound(
+            ErrorCode::NotFound,
+            format!(
                 "Could not find a value of type \"{}\" in the database.",
                 std::any::type_name::<T>(),
             ),
+        )
+    }
+
+    pub fn duplicate_index_key() -> Self {
+        Error::Storage("Another item already has a key of a unique index.".to_owned())
+    }
+
+    pub fn name_taken(name: &str) -> Self {
+        Error::Conflict(
+            ErrorCode::NameTaken,
+            format!(
+                "The name \"{}\" is already taken.",
+                name,
+            ),
+        )
+    }
+
+    pub fn no_such_user(name: &str) -> Self {
+        Error::NotFound(
+            ErrorCode::NotFound,
+            format!(
+                "There is no user named \"{}\".",
+                name,
+            ),
+        )
+    }
+
+    pub fn invalid_login() -> Self {
+        Error::Unauthorized(
+            ErrorCode::InvalidLogin,
+            "The name or password is incorrect."
+                .to_owned(),
+        )
+    }
+
+    pub fn not_in_history() -> Self {
+        Error::Validation(
+            ErrorCode::NotInHistory,
+            "The version is not in the font's history."
+                .to_owned(),
+        )
+    }
+
+    pub fn invalid_request(reason: &str) -> Self {
+        Error::Validation(
+            ErrorCode::InvalidRequest,
+            format!(
+                "The request is invalid: {}.",
+                reason,
+            ),
+        )
+    }
+
+    pub fn invalid_backup(reason: &str) -> Self {
+        Error::Validation(
+            ErrorCode::InvalidBackup,
+            format!(
+                "The backup can't be restored because {}.",
+                reason,
+            ),
+        )
+    }
+
+    pub fn database_not_empty() -> Self {
+        Error::Conflict(
+            ErrorCode::DatabaseNotEmpty,
+            "A backup can only be restored into an empty database."
+                .to_owned(),
+        )
+    }
+
+    pub fn rate_limited(retry_after: Duration) -> Self {
+        Error::RateLimited(format!(
+            "Too many requests were made. Try again in {:.1} seconds.",
+            retry_after.as_secs_f64(),
+        ))
+    }
+
+    pub fn not_logged_in() -> Self {
+        Error::Unauthorized(
+            ErrorCode::NotLoggedIn,
+            "You must be logged in to do this."
+                .to_owned(),
+        )
+    }
+
+    pub fn not_operator() -> Self {
+        Error::Forbidden(
+            ErrorCode::NotOperator,
+            "Only operators can do this."
+                .to_owned(),
+        )
+    }
+
+    pub fn address_not_allowed() -> Self {
+        Error::Forbidden(
+            ErrorCode::AddressNotAllowed,
+            "Requests from this address can't do this."
+                .to_owned(),
+        )
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::NotFound(code, _)
+            | Error::Unauthorized(code, _)
+            | Error::Validation(code, _)
+            | Error::Conflict(code, _)
+            | Error::Forbidden(code, _) => *code,
+            Error::RateLimited(_) => ErrorCode::RateLimited,
+            Error::Storage(_) => ErrorCode::Storage,
+            Error::Decode(_) => ErrorCode::Decode,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound(..) => StatusCode::NotFound,
+            Error::Unauthorized(..) => StatusCode::Unauthorized,
+            Error::Forbidden(..) => StatusCode::Forbidden,
+            Error::Validation(..) => StatusCode::BadRequest,
+            Error::Conflict(..) => StatusCode::Conflict,
+            Error::RateLimited(_) => StatusCode::TooManyRequests,
+            Error::Storage(_) | Error::Decode(_) => StatusCode::InternalServerError,
         }
     }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::NotFound(_, message)
+            | Error::Unauthorized(_, message)
+            | Error::Validation(_, message)
+            | Error::Conflict(_, message)
+            | Error::Forbidden(_, message)
+            | Error::RateLimited(message)
+            | Error::Storage(message)
+            | Error::Decode(message) => message,
+        }
+    }
+
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            code: self.code(),
+            message: self.message().to_owned(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.message().fmt(f)
+    }
 }
 
+impl std::error::Error for Error {}
+
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
         use std::io::ErrorKind::*;
 
-        Error {
-            message: match error.kind() {
-                TimedOut =>
-                    "The server took too long to do something."
-                        .to_owned(),
-
-                _ =>
-                    format!(
-                        "This IO error happened on the server: {}",
-                        error,
-                    ),
-            },
-        }
+        Error::Storage(match error.kind() {
+            TimedOut =>
+                "The server took too long to do something."
+                    .to_owned(),
+
+            _ =>
+                format!(
+                    "This IO error happened on the server: {}",
+                    error,
+                ),
+        })
     }
 }
 
@@ -77,23 +266,37 @@ impl From<sled::Error> for Error {
                 Error::from(error),
 
             _ =>
-                Error {
-                    message: format!(
-                        "A problem occured with the server's database: {}",
-                        error,
-                    ),
-                },
+                Error::Storage(format!(
+                    "A problem occured with the server's database: {}",
+                    error,
+                )),
         }
     }
 }
 
 impl From<deku::DekuError> for Error {
     fn from(error: deku::DekuError) -> Self {
-        Error {
-            message: format!(
-                "Could not deserialize/serialize data: {}",
-                error,
-            ),
-        }
+        Error::Decode(format!(
+            "Could not deserialize/serialize data: {}",
+            error,
+        ))
+    }
+}
+
+impl From<argon2::Error> for Error {
+    fn from(error: argon2::Error) -> Self {
+        Error::Storage(format!(
+            "Could not hash the password: {}",
+            error,
+        ))
+    }
+}
+
+impl From<getrandom::Error> for Error {
+    fn from(error: getrandom::Error) -> Self {
+        Error::Storage(format!(
+            "Could not generate random bytes: {}",
+            error,
+        ))
     }
 }
@@ -0,0 +1,154 @@
+use shared::glyph::{Glyph};
+
+/// Scores glyphs from their shapes alone, so generations can be run without human testers.
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Width and height of the bitmaps that glyphs are rasterized into
+    pub resolution: usize,
+    /// The number of straight lines used for each curve when rasterizing
+    pub segments: u16,
+    pub distinctness_weight: f64,
+    pub similarity_weight: f64,
+    pub contrast_weight: f64,
+    /// The most generations that one request can run, since each holds up the request while glyphs are rasterized
+    pub max_generations: u32,
+}
+
+/// Scores from 0 (worst) to 1 (best)
+pub struct Evaluation {
+    /// How different the glyph is from the most similar glyph for another character
+    pub distinctness: f64,
+    /// How much the glyph overlaps the reference template for its character, or `None` without a template
+    pub similarity: Option<f64>,
+    /// How clearly the strokes stand out from the background. Glyphs that are nearly empty or nearly solid get low scores.
+    pub contrast: f64,
+}
+
+/// A black and white image of a glyph, stored row by row
+pub struct Bitmap {
+    size: usize,
+    pixels: Vec<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            resolution: 32,
+            segments: 8,
+            distinctness_weight: 1.0,
+            similarity_weight: 1.0,
+            contrast_weight: 0.5,
+            max_generations: 100,
+        }
+    }
+}
+
+impl Config {
+    pub fn rasterize(&self, glyph: &Glyph) -> Bitmap {
+        Bitmap::new(glyph, self.resolution, self.segments)
+    }
+
+    /// `others` are the bitmaps of the other characters in the font version.
+    pub fn evaluate<'a>(
+        &self,
+        bitmap: &Bitmap,
+        others: impl Iterator<Item = &'a Bitmap>,
+        template: Option<&Bitmap>,
+    ) -> Evaluation {
+        Evaluation {
+            distinctness: others
+                .map(|other| 1.0 - bitmap.overlap(other))
+                .min_by(f64::total_cmp)
+                .unwrap_or(1.0),
+            similarity: template.map(|template| bitmap.overlap(template)),
+            contrast: {
+                let ink = bitmap.ink();
+                4.0 * ink * (1.0 - ink)
+            },
+        }
+    }
+
+    /// Combines the scores into a fitness from 0 to 1. Like `fitness::Config::fitness`, lower is better.
+    pub fn fitness(&self, evaluation: &Evaluation) -> f64 {
+        let mut total = (evaluation.distinctness * self.distinctness_weight)
+            + (evaluation.contrast * self.contrast_weight);
+        let mut total_weight = self.distinctness_weight + self.contrast_weight;
+        if let Some(similarity) = evaluation.similarity {
+            total += similarity * self.similarity_weight;
+            total_weight += self.similarity_weight;
+        }
+
+        if total_weight <= 0.0 {
+            return 1.0;
+        }
+        1.0 - (total / total_weight)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let weights = [self.distinctness_weight, self.similarity_weight, self.contrast_weight];
+        if self.resolution == 0 || self.segments == 0 || self.max_generations == 0 {
+            Err("evaluator resolution, segments and max generations must be positive".to_owned())
+        } else if weights.iter().any(|weight| weight.is_nan() || *weight < 0.0) {
+            Err("evaluator weights can't be negative".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Bitmap {
+    /// Fills the glyph's paths with the nonzero rule, like SVG does, sampling the center of each pixel.
+    pub fn new(glyph: &Glyph, size: usize, segments: u16) -> Self {
+        let polygons = glyph.to_polygons(segments);
+        let scale = 32768.0 / size as f32;
+        let mut pixels = vec![false; size * size];
+
+        for row in 0..size {
+            let y = (row as f32 + 0.5) * scale;
+            for column in 0..size {
+                let x = (column as f32 + 0.5) * scale;
+                let mut winding = 0;
+                for polygon in &polygons {
+                    for (index, start) in polygon.iter().enumerate() {
+                        let end = polygon[(index + 1) % polygon.len()];
+                        let crosses = (start[1] <= y) != (end[1] <= y);
+                        if crosses {
+                            let crossing_x = start[0] + ((y - start[1]) / (end[1] - start[1])) * (end[0] - start[0]);
+                            if crossing_x > x {
+                                winding += if end[1] > start[1] { 1 } else { -1 };
+                            }
+                        }
+                    }
+                }
+                pixels[(row * size) + column] = winding != 0;
+            }
+        }
+
+        Bitmap { size, pixels }
+    }
+
+    /// The fraction of pixels that are filled
+    pub fn ink(&self) -> f64 {
+        match self.pixels.len() {
+            0 => 0.0,
+            len => self.pixels.iter().filter(|&&pixel| pixel).count() as f64 / len as f64,
+        }
+    }
+
+    /// Intersection over union of the filled pixels, from 0 (no overlap) to 1 (identical). Two empty bitmaps are identical.
+    pub fn overlap(&self, other: &Bitmap) -> f64 {
+        if self.size != other.size {
+            return 0.0;
+        }
+        let mut intersection = 0;
+        let mut union = 0;
+        for (&a, &b) in self.pixels.iter().zip(&other.pixels) {
+            intersection += usize::from(a && b);
+            union += usize::from(a || b);
+        }
+        match union {
+            0 => 1.0,
+            union => intersection as f64 / union as f64,
+        }
+    }
+}
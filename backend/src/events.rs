@@ -0,0 +1,70 @@
+// Broadcasts `FontEvent`s to the clients watching each font, for `GET /api/fonts/:font/events`. Events aren't stored, so clients that connect later only get the current version.
+
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use crate::database::{Id};
+use crate::font::{Font};
+use shared::event::{FontEvent, LiveGlyph};
+use std::collections::{BTreeMap};
+use std::sync::{Mutex, PoisonError};
+
+/// Events that each subscriber can fall behind by before it's dropped
+const BUFFER: usize = 64;
+
+#[derive(Default)]
+pub struct Events {
+    watchers: Mutex<BTreeMap<Id<Font>, Watchers>>,
+}
+
+#[derive(Default)]
+struct Watchers {
+    senders: Vec<Sender<FontEvent>>,
+    /// The version, glyph and fitness of the last `FontEvent::BestScore` of each character
+    best_scores: BTreeMap<char, (u64, u64, Option<f64>)>,
+}
+
+impl Events {
+    pub fn subscribe(&self, font_id: Id<Font>) -> Receiver<FontEvent> {
+        let (sender, receiver) = channel::bounded(BUFFER);
+        let mut watchers = self.lock();
+        let senders = &mut watchers.entry(font_id).or_default().senders;
+        // Fonts without events would otherwise keep the senders of every client that has left
+        senders.retain(|sender| !sender.is_closed());
+        senders.push(sender);
+        receiver
+    }
+
+    /// Lets callers skip building events that no one would get
+    pub fn is_watched(&self, font_id: Id<Font>) -> bool {
+        self.lock().get(&font_id).is_some_and(|watchers| watchers.senders.iter().any(|sender| !sender.is_closed()))
+    }
+
+    /// Subscribers that disconnected or fell `BUFFER` events behind are dropped, so a slow client can't hold up the others. Browsers reconnect by themselves, and get the current version again.
+    pub fn publish(&self, font_id: Id<Font>, event: FontEvent) {
+        let mut watchers = self.lock();
+        if let Some(font_watchers) = watchers.get_mut(&font_id) {
+            font_watchers.senders.retain(|sender| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_) | TrySendError::Closed(_)) => false,
+            });
+            if font_watchers.senders.is_empty() {
+                watchers.remove(&font_id);
+            }
+        }
+    }
+
+    /// Publishes a `FontEvent::BestScore`, unless the last one for the glyph's character had the same version, glyph and fitness
+    pub fn publish_best_score(&self, font_id: Id<Font>, version: u64, glyph: LiveGlyph) {
+        let best_score = (version, glyph.glyph, glyph.fitness);
+        match self.lock().get_mut(&font_id) {
+            Some(watchers) if watchers.best_scores.get(&glyph.char) != Some(&best_score) => {
+                watchers.best_scores.insert(glyph.char, best_score);
+            },
+            _ => return,
+        }
+        self.publish(font_id, FontEvent::BestScore { version, glyph });
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<Id<Font>, Watchers>> {
+        self.watchers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
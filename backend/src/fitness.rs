@@ -0,0 +1,186 @@
+use crate::baseline::{Normalization};
+use crate::database::{Id};
+use crate::font::{Font, Trial, Trials};
+use std::str::{FromStr};
+
+/// With normalization, times are near 1 (ratio) or 0 (z-score) instead of hundreds of milliseconds, so larger penalties and prior times would outweigh every measured time.
+const MAX_NORMALIZED_PENALTY: f64 = 10.0;
+
+/// Decides which glyph wins each character when a font moves on to its next version.
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub rule: SelectionRule,
+    /// A candidate stays in the queue until it has been tested this many times.
+    pub min_trials: u32,
+    /// Added to the fitness after being multiplied by the error rate, so a glyph that is always misread gets this much extra time. In the units of `Trial::normalized_time`, which are milliseconds without normalization.
+    pub error_penalty: f64,
+    /// Like `error_penalty`, but multiplied by how often the glyph is mistaken for the one character it's most often confused with. This punishes consistent confusion more than random typos.
+    pub confusion_penalty: f64,
+    /// Applied to each time before it's used for fitness. Requires `reference_font`, and penalties in the normalized units.
+    pub normalization: Normalization,
+    /// A font whose current glyphs are shown as control tests, to measure each user's `Baseline`. It doesn't evolve.
+    pub reference_font: Option<Id<Font>>,
+    /// Users only get normal tests after this many correct control tests.
+    pub min_controls: u32,
+    /// The chance of a test being a control test after the user has `min_controls` of them
+    pub control_rate: f64,
+}
+
+/// Turns a glyph's normalized times into a single fitness, using the same units. Lower is better.
+#[derive(Clone, Copy)]
+pub enum SelectionRule {
+    /// The upper end of the confidence interval for the mean time, so a glyph only wins if it's fast with high confidence. `z` is the number of standard errors above the mean, such as 1.645 for 95% one-sided confidence.
+    ConfidenceBound { z: f64 },
+    /// The mean time after combining the glyph's times with `prior_trials` imaginary trials of `prior_time` each, so glyphs with few trials stay near the prior. `prior_time` is in the units of `Trial::normalized_time`.
+    Posterior { prior_time: f64, prior_trials: f64 },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rule: SelectionRule::ConfidenceBound { z: 1.645 },
+            min_trials: 5,
+            error_penalty: 1000.0,
+            confusion_penalty: 1000.0,
+            normalization: Normalization::None,
+            reference_font: None,
+            min_controls: 5,
+            control_rate: 0.1,
+        }
+    }
+}
+
+/// Parses `confidence-bound:<z>` or `posterior:<prior_time>:<prior_trials>`.
+impl FromStr for SelectionRule {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let parse = |num: Option<&str>| num
+            .and_then(|num| num.parse::<f64>().ok())
+            .ok_or_else(|| format!("invalid selection rule \"{}\"", string));
+
+        let mut parts = string.split(':');
+        let rule = match parts.next() {
+            Some("confidence-bound") => SelectionRule::ConfidenceBound {
+                z: parse(parts.next())?,
+            },
+            Some("posterior") => SelectionRule::Posterior {
+                prior_time: parse(parts.next())?,
+                prior_trials: parse(parts.next())?,
+            },
+            _ => return Err(format!("unknown selection rule \"{}\"", string)),
+        };
+
+        match parts.next() {
+            Some(_) => Err(format!("too many numbers in selection rule \"{}\"", string)),
+            None => Ok(rule),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Normalization::None = self.normalization {
+            return Ok(());
+        }
+        if self.reference_font.is_none() {
+            return Err("normalization requires a reference font (set evolution.reference_font)".to_owned());
+        }
+
+        let prior_time = match self.rule {
+            SelectionRule::Posterior { prior_time, .. } => prior_time,
+            SelectionRule::ConfidenceBound { .. } => 0.0,
+        };
+        for (name, value) in [("error penalty", self.error_penalty), ("confusion penalty", self.confusion_penalty), ("prior time", prior_time)] {
+            if value.abs() > MAX_NORMALIZED_PENALTY {
+                return Err(format!(
+                    "with normalization, the {} is in normalized units instead of milliseconds, so it must be at most {}",
+                    name,
+                    MAX_NORMALIZED_PENALTY,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// `top_confusion` is the highest `Confusion::count` of the glyph. Returns `f64::INFINITY` if the glyph doesn't have enough times to be chosen.
+    pub fn fitness(&self, trials: &Trials, top_confusion: u32) -> f64 {
+        let confusion_rate = match trials.count() {
+            0 => 0.0,
+            count => f64::from(top_confusion) / count as f64,
+        };
+        self.rule.fitness(trials)
+            + (trials.error_rate() * self.error_penalty)
+            + (confusion_rate * self.confusion_penalty)
+    }
+}
+
+impl SelectionRule {
+    pub fn fitness(&self, trials: &Trials) -> f64 {
+        match *self {
+            SelectionRule::ConfidenceBound { z } => match (trials.mean(), trials.variance()) {
+                (Some(mean), Some(variance)) => {
+                    let standard_error = (variance / trials.times.len() as f64).sqrt();
+                    mean + (z * standard_error)
+                },
+                _ => f64::INFINITY,
+            },
+            SelectionRule::Posterior { prior_time, prior_trials } => {
+                let count = trials.times.len() as f64;
+                if count + prior_trials <= 0.0 {
+                    return f64::INFINITY;
+                }
+                let sum: f64 = trials.times.iter().map(|trial| trial.normalized_time).sum();
+                (sum + (prior_time * prior_trials)) / (count + prior_trials)
+            },
+        }
+    }
+}
+
+// Statistics use `Trial::normalized_time`
+impl Trials {
+    /// The number of tests, including errors
+    pub fn count(&self) -> usize {
+        self.times.len() + self.errors as usize
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.times.is_empty() {
+            return None;
+        }
+        let sum: f64 = self.times.iter().map(|trial| trial.normalized_time).sum();
+        Some(sum / self.times.len() as f64)
+    }
+
+    /// Sample variance, which needs at least 2 times
+    pub fn variance(&self) -> Option<f64> {
+        let mean = self.mean()?;
+        if self.times.len() < 2 {
+            return None;
+        }
+        let sum_of_squares: f64 = self.times.iter().map(|trial| (trial.normalized_time - mean).powi(2)).sum();
+        Some(sum_of_squares / (self.times.len() - 1) as f64)
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        let mut times: Vec<f64> = self.times.iter().map(|trial| trial.normalized_time).collect();
+        times.sort_by(f64::total_cmp);
+        let middle = times.len() / 2;
+        match times.len() {
+            0 => None,
+            len if len % 2 == 0 => Some((times[middle - 1] + times[middle]) / 2.0),
+            _ => Some(times[middle]),
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        match self.count() {
+            0 => 0.0,
+            count => f64::from(self.errors) / count as f64,
+        }
+    }
+
+    pub fn fastest(&self) -> Option<&Trial> {
+        self.times.iter().min_by(|a, b| a.normalized_time.total_cmp(&b.normalized_time))
+    }
+}
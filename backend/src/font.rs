@@ -1,8 +1,9 @@
+use crate::active_test::{TestKind};
 use crate::database::{Id};
 use crate::user::{User};
 use deku::prelude::*;
-use shared::glyph::{Glyph};
-use shared::util::{char_map, char_write};
+use shared::glyph::{Glyph, Mutation};
+use shared::util::{char_map, char_write, read_rest, read_whole_items};
 
 #[derive(DekuRead, DekuWrite)]
 #[deku(endian = "big")]
@@ -10,8 +11,14 @@ pub struct Font {
     pub first_version: Id<Version>,
     pub current_version: Id<Version>,
     // A queue of glyphs that still need to be tested
-    #[deku(bits_read = "deku::rest.len()")]
+    #[deku(reader = "read_whole_items(deku::rest, 64, deku::ctx::Endian::Big)")]
     pub candidates: Vec<Id<Glyph>>,
+    // Fonts stored before test kinds were added end after the candidates, and were tested with single glyphs
+    #[deku(cond = "!deku::rest.is_empty()", default = "TestKind::Single")]
+    pub test_kind: TestKind,
+    // How many of the current version's candidates haven't been made and queued yet. Fonts stored before candidates were queued gradually had all of them in the queue.
+    #[deku(cond = "!deku::rest.is_empty()", default = "0")]
+    pub unqueued_candidates: u32,
 }
 
 #[derive(DekuRead, DekuWrite)]
@@ -19,11 +26,26 @@ pub struct Font {
 pub struct Version {
     // An ID that might not be in use yet
     pub next_version: Id<Version>,
+    // Versions stored before the rest was recorded only have `next_version`. They're read with the defaults, which are what their candidates were made with, except for the unknown creation time and seed.
+    /// Milliseconds since the Unix epoch, or 0 if unknown
+    #[deku(cond = "!deku::rest.is_empty()", default = "0")]
+    pub created: u64,
+    /// Seeds the random mutations of the version's candidates, so they can be reproduced. 0 if unknown.
+    #[deku(cond = "!deku::rest.is_empty()", default = "0")]
+    pub seed: u64,
+    #[deku(cond = "!deku::rest.is_empty()", default = "Mutation::default()")]
+    pub mutation: Mutation,
+    /// The `worker::Config::min_candidates` that the candidates were made with
+    #[deku(cond = "!deku::rest.is_empty()", default = "1")]
+    pub min_candidates: u32,
+    /// The version whose winners are this version's glyphs, or `None` for a font's first version
+    #[deku(cond = "!deku::rest.is_empty()")]
+    pub parent: Option<Id<Version>>,
 }
 
 /// Identifies a `Version` and one of its glyphs.
 #[derive(DekuRead, DekuWrite, Clone, Copy)]
-#[deku(endian = "big")]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
 pub struct VersionGlyphKey {
     pub font_version: Id<Version>,
     #[deku(map = "char_map", writer = "char_write(deku::output, char)")]
@@ -34,13 +56,69 @@ pub struct VersionGlyphKey {
 #[deku(endian = "big")]
 pub struct VersionGlyph {
     pub glyph: Id<Glyph>,
-    #[deku(cond = "deku::rest.len() != 0")]
+    #[deku(cond = "!deku::rest.is_empty()")]
     pub score: Option<Score>,
 }
 
+/// The result of the trials that made a glyph win its place in a `Version`.
 #[derive(DekuRead, DekuWrite)]
-#[deku(endian = "big", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
 pub struct Score {
+    /// The fitness calculated by `fitness::Config`, in the units of `Trial::normalized_time`. Scores stored before fitness was calculated have the fastest time in milliseconds instead, which is the fitness of that one trial without normalization or penalties.
+    pub fitness: f64,
+    /// The user with the fastest trial
+    pub user: Id<User>,
+}
+
+/// Where a candidate came from. Glyphs that were added with a font don't have one.
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct GlyphOrigin {
+    /// The glyph that was mutated to make this one
+    pub parent: Id<Glyph>,
+    /// The version that the glyph was a candidate in
+    pub font_version: Id<Version>,
+}
+
+/// Identifies a glyph that is competing to be `version_glyph.char` in the version after `version_glyph.font_version`.
+#[derive(DekuRead, DekuWrite, Clone, Copy)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
+pub struct TrialsKey {
+    pub version_glyph: VersionGlyphKey,
+    pub glyph: Id<Glyph>,
+}
+
+/// Every test result for one glyph in one `Version`.
+#[derive(DekuRead, DekuWrite, Default)]
+#[deku(endian = "big")]
+pub struct Trials {
+    /// The number of tests where a wrong character was typed, which aren't included in `times`
+    pub errors: u32,
+    #[deku(reader = "read_rest(deku::rest, deku::ctx::Endian::Big)")]
+    pub times: Vec<Trial>,
+}
+
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
+pub struct Trial {
+    /// Milliseconds between showing the glyph and typing the correct character
     pub time: f64,
+    /// `time` after `fitness::Config::normalization` was applied
+    pub normalized_time: f64,
     pub user: Id<User>,
 }
+
+/// Identifies a character that was typed instead of `trials.version_glyph.char` when a glyph was shown.
+#[derive(DekuRead, DekuWrite, Clone, Copy)]
+#[deku(endian = "big")]
+pub struct ConfusionKey {
+    pub trials: TrialsKey,
+    #[deku(map = "char_map", writer = "char_write(deku::output, typed)")]
+    pub typed: char,
+}
+
+#[derive(DekuRead, DekuWrite, Default)]
+#[deku(endian = "big")]
+pub struct Confusion {
+    pub count: u32,
+}
@@ -0,0 +1,22 @@
+// The code generated by deku's derive macros doesn't use `div_ceil`
+#![allow(clippy::manual_div_ceil)]
+
+pub mod active_test;
+pub mod assets;
+pub mod backup;
+pub mod baseline;
+pub mod config;
+pub mod database;
+pub mod endpoints;
+pub mod error;
+pub mod evaluator;
+pub mod events;
+pub mod fitness;
+pub mod font;
+pub mod metrics;
+pub mod rate_limit;
+pub mod state;
+pub mod stats;
+pub mod storage;
+pub mod user;
+pub mod worker;
@@ -1,13 +1,9 @@
-mod active_test;
-mod database;
-mod endpoints;
-mod error;
-mod font;
-mod state;
-mod user;
-
-use crate::state::{State};
-use crate::error::{InitError, Error};
+use backend::{endpoints, worker};
+use backend::config::{Config};
+use backend::database::{Database};
+use backend::rate_limit::{Limiter};
+use backend::state::{State};
+use backend::error::{InitError};
 
 fn main() {
     if let Err(error) = async_std::task::block_on(run_server()) {
@@ -16,14 +12,22 @@ fn main() {
 }
 
 async fn run_server() -> Result<(), InitError> {
-    let address: Box<str> = std::env::var("ADDRESS")
-        .map(String::into_boxed_str)
-        .unwrap_or(Box::from("127.0.0.1:8080"));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (config, rest) = Config::load(&args)?;
+    if let Some(arg) = rest.first() {
+        return Err(InitError::Config(format!("unexpected argument \"{}\"", arg)));
+    }
+
+    let (worker, wake) = worker::channel(config.worker);
+    let db = Database::open(&config.data_directory, config.storage_mode).await?;
+    let limiter = Limiter::new(config.rate_limit, &db).await?;
+    let state = State::new(db, config.fitness, config.evaluator, worker, config.passwords).await?;
+    async_std::task::spawn(worker::run(state.clone(), wake));
 
-    let mut server = tide::with_state(State::new().await?);
-    endpoints::init(&mut server)?;
-    println!("Running server at {}", address);
-    server.listen(&*address).await?;
+    let mut server = tide::with_state(state);
+    endpoints::init(&mut server, &config, limiter)?;
+    println!("Running server at {}", config.address);
+    server.listen(&*config.address).await?;
 
     Ok(())
 }
@@ -0,0 +1,191 @@
+// Counters and histograms for `GET /metrics`, in the Prometheus text format. Values that can be read from the database, such as tree sizes, are `Gauge`s made for each scrape instead.
+
+use std::collections::{BTreeMap};
+use std::fmt::{Write as _};
+use std::sync::{Mutex, PoisonError};
+
+/// Upper bounds in seconds, for how long testers take to finish a test
+const LATENCY_BUCKETS: &[f64] = &[0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+/// Upper bounds in seconds, for how long requests take
+const DURATION_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Everything that's counted since the server started
+pub struct Metrics {
+    pub tests_served: Counter,
+    pub tests_submitted: Counter,
+    /// From serving a test to the submission that finished it
+    pub submission_latency: Histogram,
+    pub generations: Counter,
+    pub request_duration: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            tests_served: Counter::new("fontgen_tests_served_total", "Tests given to testers", &["kind"]),
+            tests_submitted: Counter::new(
+                "fontgen_tests_submitted_total",
+                "Tests finished by testers, by whether the glyph was read correctly",
+                &["kind", "result"],
+            ),
+            submission_latency: Histogram::new(
+                "fontgen_submission_latency_seconds",
+                "Time from serving a test to finishing it",
+                &["kind"],
+                LATENCY_BUCKETS,
+            ),
+            generations: Counter::new("fontgen_generations_total", "Versions created by evolution", &["font"]),
+            request_duration: Histogram::new(
+                "fontgen_request_duration_seconds",
+                "Time to respond to API requests",
+                &["method", "route", "status"],
+                DURATION_BUCKETS,
+            ),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn render(&self, gauges: &[Gauge]) -> String {
+        let mut output = String::new();
+        self.tests_served.render(&mut output);
+        self.tests_submitted.render(&mut output);
+        self.submission_latency.render(&mut output);
+        self.generations.render(&mut output);
+        self.request_duration.render(&mut output);
+        for gauge in gauges {
+            gauge.render(&mut output);
+        }
+        output
+    }
+}
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    /// By label values, in the order of `labels`
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl Counter {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Counter {
+            name,
+            help,
+            labels,
+            values: Mutex::default(),
+        }
+    }
+
+    pub fn increment(&self, label_values: &[&str]) {
+        let mut values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        *values.entry(owned(label_values)).or_insert(0) += 1;
+    }
+
+    fn render(&self, output: &mut String) {
+        write_header(output, self.name, self.help, "counter");
+        for (label_values, value) in self.values.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            write_sample(output, self.name, self.labels, label_values, None, *value as f64);
+        }
+    }
+}
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    buckets: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, Observations>>,
+}
+
+#[derive(Default)]
+struct Observations {
+    /// Observations in each bucket and not in the ones before it. The last one is for values above every bucket.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, labels: &'static [&'static str], buckets: &'static [f64]) -> Self {
+        Histogram {
+            name,
+            help,
+            labels,
+            buckets,
+            values: Mutex::default(),
+        }
+    }
+
+    pub fn observe(&self, label_values: &[&str], value: f64) {
+        let mut values = self.values.lock().unwrap_or_else(PoisonError::into_inner);
+        let observations = values.entry(owned(label_values)).or_default();
+        observations.counts.resize(self.buckets.len() + 1, 0);
+        let bucket = self.buckets.iter().position(|&bound| value <= bound).unwrap_or(self.buckets.len());
+        observations.counts[bucket] += 1;
+        observations.sum += value;
+    }
+
+    /// Buckets are cumulative in the output
+    fn render(&self, output: &mut String) {
+        write_header(output, self.name, self.help, "histogram");
+        let bucket_name = format!("{}_bucket", self.name);
+        for (label_values, observations) in self.values.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            let mut count = 0;
+            for (bound, bucket_count) in self.buckets.iter().map(f64::to_string).chain(["+Inf".to_owned()]).zip(&observations.counts) {
+                count += bucket_count;
+                write_sample(output, &bucket_name, self.labels, label_values, Some(&bound), count as f64);
+            }
+            write_sample(output, &format!("{}_sum", self.name), self.labels, label_values, None, observations.sum);
+            write_sample(output, &format!("{}_count", self.name), self.labels, label_values, None, count as f64);
+        }
+    }
+}
+
+/// A value for each set of labels, measured when the metrics are requested
+pub struct Gauge {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub labels: &'static [&'static str],
+    pub values: Vec<(Vec<String>, f64)>,
+}
+
+impl Gauge {
+    fn render(&self, output: &mut String) {
+        write_header(output, self.name, self.help, "gauge");
+        for (label_values, value) in &self.values {
+            write_sample(output, self.name, self.labels, label_values, None, *value);
+        }
+    }
+}
+
+fn owned(label_values: &[&str]) -> Vec<String> {
+    label_values.iter().map(|&value| value.to_owned()).collect()
+}
+
+fn write_header(output: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} {}", name, kind).unwrap();
+}
+
+/// `bucket` is the upper bound of a histogram bucket, which is written as the `le` label.
+fn write_sample(output: &mut String, name: &str, labels: &[&str], label_values: &[String], bucket: Option<&str>, value: f64) {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .zip(label_values)
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+        .collect();
+    if let Some(bucket) = bucket {
+        pairs.push(format!("le=\"{}\"", bucket));
+    }
+
+    output.push_str(name);
+    if !pairs.is_empty() {
+        write!(output, "{{{}}}", pairs.join(",")).unwrap();
+    }
+    writeln!(output, " {}", value).unwrap();
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
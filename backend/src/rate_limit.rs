@@ -0,0 +1,170 @@
+// Token buckets that limit how often each user and each client address can start and submit tests. The buckets are kept in memory, and also in the database if `Config::persist` is set.
+
+use async_std::stream::{StreamExt};
+use crate::database::{Database, Id, Tree};
+use crate::error::{InitError, Error as E};
+use crate::state::{now};
+use crate::user::{User};
+use deku::prelude::*;
+use shared::util::{read_rest};
+use std::collections::{HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration};
+
+/// More buckets than this are pruned of full ones, which are the same as missing ones
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Requests that can be made at once after a pause
+    pub burst: f64,
+    /// Requests allowed each second after the burst is used up
+    pub per_second: f64,
+    /// Keeps the buckets in the database, so restarting the server doesn't reset them
+    pub persist: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            burst: 60.0,
+            per_second: 5.0,
+            persist: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.burst >= 1.0 && self.burst.is_finite()) {
+            Err("rate limit burst must be at least 1".to_owned())
+        } else if !(self.per_second > 0.0 && self.per_second.is_finite()) {
+            Err("rate limit per second must be positive".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Identifies a bucket, such as "user 12" or "address 127.0.0.1"
+#[derive(DekuRead, DekuWrite, Clone, PartialEq, Eq, Hash)]
+#[deku(endian = "big")]
+pub struct Key {
+    #[deku(reader = "read_rest(deku::rest, deku::ctx::Endian::Big)")]
+    bytes: Vec<u8>,
+}
+
+impl Key {
+    pub fn user(id: Id<User>) -> Self {
+        Key {
+            bytes: format!("user {}", id).into_bytes(),
+        }
+    }
+
+    /// Without the port, so every connection from a client shares the bucket
+    pub fn address(address: &str) -> Self {
+        let address = address
+            .parse::<std::net::SocketAddr>()
+            .map_or(address.to_owned(), |address| address.ip().to_string());
+        Key {
+            bytes: format!("address {}", address).into_bytes(),
+        }
+    }
+}
+
+#[derive(DekuRead, DekuWrite, Clone, Copy)]
+#[deku(endian = "big")]
+struct Bucket {
+    tokens: f64,
+    /// Milliseconds since the Unix epoch when `tokens` was last changed
+    updated: u64,
+}
+
+#[derive(Clone)]
+pub struct Limiter {
+    config: Config,
+    buckets: Arc<Mutex<HashMap<Key, Bucket>>>,
+    /// Only opened if `Config::persist` is set
+    tree: Option<Tree<Bucket, Key>>,
+}
+
+impl Limiter {
+    /// Loads the stored buckets if `config.persist` is set.
+    pub async fn new(config: Config, db: &Database) -> Result<Self, InitError> {
+        let mut buckets = HashMap::new();
+        let tree = if config.persist {
+            let tree: Tree<Bucket, Key> = db.tree(b"rate_limits").await?;
+            let mut stream = tree.iter();
+            while let Some(result) = stream.next().await {
+                let (key, bucket) = result?;
+                buckets.insert(key, bucket);
+            }
+            Some(tree)
+        } else {
+            None
+        };
+
+        Ok(Limiter {
+            config,
+            buckets: Arc::new(Mutex::new(buckets)),
+            tree,
+        })
+    }
+
+    /// Takes a token from each bucket, or none of them if any bucket is empty. Returns how long to wait for the empty buckets to have a token.
+    pub async fn take(&self, keys: &[Key]) -> Result<Result<(), Duration>, E> {
+        let now = now();
+        let (changed, pruned) = {
+            let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+            let refilled: Vec<Bucket> = keys.iter().map(|key| self.refill(buckets.get(key), now)).collect();
+            let shortest = refilled.iter().map(|bucket| bucket.tokens).fold(f64::INFINITY, f64::min);
+            if shortest < 1.0 {
+                return Ok(Err(Duration::from_secs_f64((1.0 - shortest) / self.config.per_second)));
+            }
+
+            let mut changed = Vec::with_capacity(keys.len());
+            for (key, mut bucket) in keys.iter().zip(refilled) {
+                bucket.tokens -= 1.0;
+                buckets.insert(key.clone(), bucket);
+                changed.push((key.clone(), bucket));
+            }
+
+            let mut pruned = Vec::new();
+            if buckets.len() > MAX_BUCKETS {
+                buckets.retain(|key, bucket| {
+                    let full = self.refill(Some(bucket), now).tokens >= self.config.burst;
+                    if full {
+                        pruned.push(key.clone());
+                    }
+                    !full
+                });
+            }
+            (changed, pruned)
+        };
+
+        if let Some(tree) = &self.tree {
+            for (key, bucket) in changed {
+                tree.insert_with_key(key, &bucket).await?;
+            }
+            for key in pruned {
+                tree.remove(key).await?;
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// Adds the tokens earned since the bucket was updated. A missing bucket is full.
+    fn refill(&self, bucket: Option<&Bucket>, now: u64) -> Bucket {
+        let tokens = match bucket {
+            Some(bucket) => {
+                let seconds = now.saturating_sub(bucket.updated) as f64 / 1000.0;
+                f64::min(self.config.burst, bucket.tokens + seconds * self.config.per_second)
+            },
+            None => self.config.burst,
+        };
+        Bucket {
+            tokens,
+            updated: now,
+        }
+    }
+}
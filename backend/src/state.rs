@@ -1,120 +1,625 @@
+use async_std::channel::{Receiver};
 use async_std::stream::{Stream, StreamExt};
-use crate::active_test::{ActiveTest};
-use crate::database::{Database, Id, Tree};
+use async_std::sync::{Mutex};
+use crate::active_test::{ActiveTest, Expiry, Keystroke, Outcome, TestKind, BLUR, CHOICE_COUNT, MIN_TIME, SMALL_SIZE, WORD_LENGTH};
+use crate::baseline::{Baseline};
+use crate::database::{Database, Id, Index, TransactionError, TransactionalTree, Tree};
+use crate::evaluator;
+use crate::events::{Events};
+use crate::fitness;
 use crate::font::{self, Font};
-use crate::user::{User};
+use crate::metrics::{Gauge, Metrics};
+use crate::stats;
+use crate::user::{self, HashParams, User};
+use crate::worker;
 use crate::error::{InitError, Error as E};
+use shared::diff::{self, CharDiff, DiffSide, VersionDiff};
+use shared::event::{FontEvent, LiveGlyph};
 use shared::glyph::{Glyph};
+use shared::history::{Ancestor, CandidateSummary, VersionGlyphSummary, VersionSummary};
+use shared::presentation::{Presentation};
+use shared::report::{ConfusionMatrix, GarbageReport, IntegrityReport, Problem};
+use shared::stats::{FontStats, Leaderboard, LeaderboardEntry, UserStats};
+use shared::util::{DekuRW};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Range};
+use std::sync::{Arc};
+
+/// The most items that `State::collect_garbage` deletes in one transaction
+const GC_BATCH_SIZE: usize = 256;
 
 #[derive(Clone)]
 pub struct State {
     active_tests: Tree<ActiveTest, Id<User>>,
+    baselines: Tree<Baseline, Id<User>>,
+    confusions: Tree<font::Confusion, font::ConfusionKey>,
+    db: Database,
+    evaluator: evaluator::Config,
+    events: Arc<Events>,
+    fitness: fitness::Config,
     font_version_glyphs: Tree<font::VersionGlyph, font::VersionGlyphKey>,
     font_versions: Tree<font::Version>,
     fonts: Tree<Font>,
+    /// Counts forks, which can make garbage reachable again. It's locked while a font is forked and while garbage is deleted, so garbage is only deleted if no font was forked since it was found.
+    forks: Arc<Mutex<u64>>,
+    glyph_origins: Tree<font::GlyphOrigin, Id<Glyph>>,
     glyphs: Tree<Glyph>,
+    metrics: Arc<Metrics>,
+    /// The costs of each user's password hash. Users without them were added with `HashParams::default()`.
+    password_params: Tree<HashParams, Id<User>>,
+    /// Used for new password hashes
+    passwords: HashParams,
+    /// Only has tests finished since it was added
+    tester_stats: Tree<stats::Tests, stats::Key>,
+    trials: Tree<font::Trials, font::TrialsKey>,
+    users: Tree<User>,
+    users_by_name: Index<User, Id<User>, user::Name>,
+    worker: worker::Handle,
+}
+
+/// The trees of `State` that are changed by tests and new versions, inside a transaction.
+struct Txn {
+    active_tests: TransactionalTree<ActiveTest, Id<User>>,
+    baselines: TransactionalTree<Baseline, Id<User>>,
+    confusions: TransactionalTree<font::Confusion, font::ConfusionKey>,
+    font_version_glyphs: TransactionalTree<font::VersionGlyph, font::VersionGlyphKey>,
+    font_versions: TransactionalTree<font::Version>,
+    fonts: TransactionalTree<Font>,
+    glyph_origins: TransactionalTree<font::GlyphOrigin, Id<Glyph>>,
+    glyphs: TransactionalTree<Glyph>,
+    password_params: TransactionalTree<HashParams, Id<User>>,
+    tester_stats: TransactionalTree<stats::Tests, stats::Key>,
+    trials: TransactionalTree<font::Trials, font::TrialsKey>,
+    users: TransactionalTree<User>,
+}
+
+/// Versions and glyphs that `State::find_garbage` found unreachable
+pub struct Garbage {
+    versions: Vec<Id<font::Version>>,
+    glyphs: Vec<Id<Glyph>>,
+    /// `State::forks` when the garbage was found
+    forks: u64,
+}
+
+/// A test that was finished by a submission
+struct Submitted {
+    kind: TestKind,
+    /// `ActiveTest::served`
+    served: u64,
+    /// Whether the glyph was read correctly
+    correct: bool,
+    font: Id<Font>,
+    /// Where the result was recorded, or `None` for control tests and tests of an older version
+    trials: Option<font::TrialsKey>,
+    /// Whether the test removed the last candidate of its font's version from the queue
+    queue_emptied: bool,
+    /// Whether the test left fewer than `worker::Config::queue_depth` candidates in the queue while more can be made
+    queue_short: bool,
 }
 
 impl State {
-    pub async fn new() -> Result<Self, InitError> {
-        let db = Database::open().await?;
+    pub async fn new(
+        db: Database,
+        fitness: fitness::Config,
+        evaluator: evaluator::Config,
+        worker: worker::Handle,
+        passwords: HashParams,
+    ) -> Result<Self, InitError> {
+        let mut users = db.tree(b"users").await?;
+        let users_by_name = users.add_unique_index(b"users_by_name", |user: &User| vec![user.name_key()]).await?;
 
         Ok(State {
             active_tests: db.tree(b"test_sessions").await?,
+            baselines: db.tree(b"baselines").await?,
+            confusions: db.tree(b"confusions").await?,
+            evaluator,
+            events: Arc::default(),
+            fitness,
             font_version_glyphs: db.tree(b"scores").await?,
             font_versions: db.tree(b"font_versions").await?,
             fonts: db.tree(b"fonts").await?,
+            forks: Arc::default(),
+            glyph_origins: db.tree(b"glyph_origins").await?,
             glyphs: db.tree(b"glyphs").await?,
+            metrics: Arc::default(),
+            password_params: db.tree(b"password_params").await?,
+            passwords,
+            tester_stats: db.tree(b"tester_stats").await?,
+            trials: db.tree(b"trials").await?,
+            users,
+            users_by_name,
+            db,
+            worker,
         })
     }
 
-    pub async fn add_font(&self, glyphs: Vec<Glyph>) -> Result<Id<Font>, E> {
-        let first_version_id = Id::generate(&self.font_versions).await?;
-        let _: font::Version = self.add_font_version(
-            first_version_id,
-            self.glyphs
-                .insert_each(glyphs.iter()).await?
-                .into_iter()
-                .map(|id| font::VersionGlyph {
-                    glyph: id,
-                    score: None,
-                })
-                // Convert items to borrowed items
-                .collect::<Vec<_>>()
-                .iter(),
-        ).await?;
+    pub fn worker(&self) -> &worker::Handle {
+        &self.worker
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    /// Renders the metrics with the current queue depths and tree sizes. Every font is read and every tree's items are counted, so `/metrics` is limited to `Config::metrics_clients`.
+    pub async fn render_metrics(&self) -> Result<String, E> {
+        let mut queue_depths = Vec::new();
+        let mut stream = self.fonts.iter();
+        while let Some(result) = stream.next().await {
+            let (font_id, font) = result?;
+            queue_depths.push((vec![font_id.to_string()], font.candidates.len() as f64));
+        }
+        let tree_sizes = self.db
+            .tree_sizes()
+            .await?
+            .into_iter()
+            .map(|(name, len)| (vec![name], len as f64))
+            .collect();
+
+        Ok(self.metrics.render(&[
+            Gauge {
+                name: "fontgen_candidate_queue_depth",
+                help: "Candidates waiting to be tested in each font's current version",
+                labels: &["font"],
+                values: queue_depths,
+            },
+            Gauge {
+                name: "fontgen_tree_items",
+                help: "Items in each database tree, including index trees",
+                labels: &["tree"],
+                values: tree_sizes,
+            },
+        ]))
+    }
+
+    fn record_submission(&self, submitted: &Submitted, now: u64) {
+        let kind = submitted.kind.name();
+        let result = if submitted.correct { "correct" } else { "error" };
+        self.metrics.tests_submitted.increment(&[kind, result]);
+        self.metrics.submission_latency.observe(&[kind], now.saturating_sub(submitted.served) as f64 / 1000.0);
+    }
+
+    /// Returns the font's current version as a `FontEvent::Version`, and a receiver for the font's later events.
+    pub async fn watch_font(&self, font_id: Id<Font>) -> Result<(FontEvent, Receiver<FontEvent>), E> {
+        // Checked before subscribing, so unknown fonts don't get subscriber lists
+        self.fonts.get(font_id).await?;
+        // Subscribing before reading the current version means that a version started in between isn't missed, although it may be sent twice
+        let receiver = self.events.subscribe(font_id);
+        let current = self.version_event(self.fonts.get(font_id).await?.current_version).await?;
+        Ok((current, receiver))
+    }
+
+    async fn version_event(&self, version_id: Id<font::Version>) -> Result<FontEvent, E> {
+        let version = self.font_versions.get(version_id).await?;
+        let mut glyphs = Vec::new();
+        let mut stream = self.font_version_glyphs.scan_prefix(version_id)?;
+        while let Some(result) = stream.next().await {
+            let (key, version_glyph) = result?;
+            glyphs.push(self.live_glyph(key.char, &version_glyph).await?);
+        }
+        Ok(FontEvent::Version {
+            version: version_id.into(),
+            parent: version.parent.map(Into::into),
+            glyphs,
+        })
+    }
+
+    async fn live_glyph(&self, char: char, version_glyph: &font::VersionGlyph) -> Result<LiveGlyph, E> {
+        Ok(LiveGlyph {
+            char,
+            glyph: version_glyph.glyph.into(),
+            fitness: version_glyph.score.as_ref().map(|score| score.fitness),
+            outline: self.glyphs.get(version_glyph.glyph).await?.to_svg_path_d(),
+        })
+    }
+
+    /// Publishes the font's new version, if anyone is watching the font. Errors are only logged, because the version was already saved.
+    async fn announce_version(&self, font_id: Id<Font>, version_id: Id<font::Version>) {
+        if self.events.is_watched(font_id) {
+            match self.version_event(version_id).await {
+                Ok(event) => self.events.publish(font_id, event),
+                Err(error) => eprintln!("Error while announcing a version: {}", error),
+            }
+        }
+    }
+
+    /// Publishes the tested candidate if it now has the best fitness for its character, and whether the queue was drained, if anyone is watching the font. Errors are only logged, because the submission was already saved.
+    async fn announce_submission(&self, submitted: &Submitted) {
+        let key = match submitted.trials {
+            Some(trials_key) if self.events.is_watched(submitted.font) => trials_key,
+            _ => return,
+        };
+        let version_glyph_key = key.version_glyph;
+
+        if let Err(error) = self.announce_best_score(submitted.font, key).await {
+            eprintln!("Error while announcing a best score: {}", error);
+        }
+        if submitted.queue_emptied {
+            self.events.publish(submitted.font, FontEvent::QueueDrained {
+                version: version_glyph_key.font_version.into(),
+            });
+        }
+    }
+
+    async fn announce_best_score(&self, font_id: Id<Font>, key: font::TrialsKey) -> Result<(), E> {
+        let version_glyph_key = key.version_glyph;
+        if let Some(current) = self.font_version_glyphs.get_option(version_glyph_key).await? {
+            let winner = self.select_winner(version_glyph_key, current).await?;
+            if winner.glyph == key.glyph && winner.score.is_some() {
+                let glyph = self.live_glyph(version_glyph_key.char, &winner).await?;
+                self.events.publish_best_score(font_id, version_glyph_key.font_version.into(), glyph);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `f` in a `Database::transaction` with every tree. Each state transition reads what it needs to scan first, then makes all of its changes in one transaction, which checks that the font hasn't moved on since it was read.
+    async fn transaction<R>(&self, f: impl Fn(&Txn) -> Result<R, TransactionError>) -> Result<R, E> {
+        let trees = (
+            &self.active_tests,
+            &self.baselines,
+            &self.confusions,
+            &self.font_version_glyphs,
+            &self.font_versions,
+            &self.fonts,
+            &self.glyph_origins,
+            &self.glyphs,
+            &self.password_params,
+            &self.tester_stats,
+            &self.trials,
+            &self.users,
+        );
+        self.db.transaction(trees, |(active_tests, baselines, confusions, font_version_glyphs, font_versions, fonts, glyph_origins, glyphs, password_params, tester_stats, trials, users)| {
+            f(&Txn {
+                active_tests,
+                baselines,
+                confusions,
+                font_version_glyphs,
+                font_versions,
+                fonts,
+                glyph_origins,
+                glyphs,
+                password_params,
+                tester_stats,
+                trials,
+                users,
+            })
+        }).await
+    }
+
+    pub async fn add_user(&self, name: &str, password: &str) -> Result<Id<User>, E> {
+        let user = User::new(name, password, &self.passwords)?;
+        self.db.transaction((&self.users, &self.password_params), |(users, password_params)| {
+            // Checked in the transaction, so two users can't take the same name at once
+            if users.get_unique(&self.users_by_name, user.name_key())?.is_some() {
+                return Err(E::name_taken(name).into());
+            }
+            let id = users.insert(&user)?;
+            password_params.insert_with_key(id, &self.passwords)?;
+            Ok(id)
+        }).await
+    }
+
+    /// The password is hashed again if the costs have changed since it was hashed.
+    pub async fn log_in(&self, name: &str, password: &str) -> Result<Id<User>, E> {
+        let (id, user) = self.find_user(name).await?.ok_or_else(E::invalid_login)?;
+        let params = self.password_params.get_option(id).await?.unwrap_or_default();
+        if !user.verify_password(password, &params)? {
+            return Err(E::invalid_login());
+        }
+
+        if params != self.passwords {
+            let user = User::new(name, password, &self.passwords)?;
+            self.db.transaction((&self.users, &self.password_params), |(users, password_params)| {
+                users.insert_with_key(id, &user)?;
+                password_params.insert_with_key(id, &self.passwords)
+            }).await?;
+        }
+        Ok(id)
+    }
+
+    pub async fn user_name(&self, user_id: Id<User>) -> Result<String, E> {
+        Ok(self.users.get(user_id).await?.name().into_owned())
+    }
 
-        let font = Font {
+    async fn find_user(&self, name: &str) -> Result<Option<(Id<User>, User)>, E> {
+        self.users_by_name.get(user::Name::new(name))?.next().await.transpose()
+    }
+
+    pub async fn add_font(&self, glyphs: Vec<Glyph>, test_kind: TestKind) -> Result<Id<Font>, E> {
+        self.transaction(|txn| {
+            let mut glyph_ids = Vec::with_capacity(glyphs.len());
+            for glyph in &glyphs {
+                glyph_ids.push(txn.glyphs.insert(glyph)?);
+            }
+            self.create_font(txn, &glyph_ids, test_kind)
+        }).await
+    }
+
+    /// Like `add_font`, but with glyphs that are already stored, such as ones from `import_glyphs`.
+    pub async fn add_font_with_glyphs(&self, glyph_ids: &[Id<Glyph>], test_kind: TestKind) -> Result<Id<Font>, E> {
+        self.transaction(|txn| self.create_font(txn, glyph_ids, test_kind)).await
+    }
+
+    fn create_font(&self, txn: &Txn, glyph_ids: &[Id<Glyph>], test_kind: TestKind) -> Result<Id<Font>, TransactionError> {
+        let version_glyphs: Vec<font::VersionGlyph> = glyph_ids
+            .iter()
+            .map(|&glyph| font::VersionGlyph {
+                glyph,
+                score: None,
+            })
+            .collect();
+
+        let first_version_id = txn.font_versions.generate_id()?;
+        let mut font = Font {
             first_version: first_version_id,
             current_version: first_version_id,
-            candidates: self.glyphs.insert_each(
-                Glyph::generate_variants(glyphs.iter()).iter()
-            ).await?,
+            test_kind,
+            candidates: Vec::new(),
+            unqueued_candidates: 0,
         };
-        let font_id = self.fonts.insert(&font).await?;
+        self.start_version(txn, &mut font, first_version_id, None, &version_glyphs)?;
+        txn.fonts.insert(&font)
+    }
 
-        Ok(font_id)
+    /// Stores glyphs without adding them to a font.
+    pub async fn import_glyphs(&self, glyphs: &[Glyph]) -> Result<Vec<Id<Glyph>>, E> {
+        self.glyphs.insert_each(glyphs.iter()).await
     }
 
-    async fn add_font_version(
+    fn add_font_version(
         &self,
+        txn: &Txn,
         id: Id<font::Version>,
-        version_glyphs: impl Iterator<Item = &font::VersionGlyph>,
-    ) -> Result<font::Version, E> {
+        parent: Option<Id<font::Version>>,
+        version_glyphs: &[font::VersionGlyph],
+    ) -> Result<font::Version, TransactionError> {
         let version = font::Version {
-            next_version: Id::generate(&self.font_versions).await?,
+            next_version: txn.font_versions.generate_id()?,
+            created: now(),
+            seed: fastrand::u64(..),
+            mutation: self.worker.mutation,
+            min_candidates: self.worker.min_candidates as u32,
+            parent,
         };
-        self.font_versions.insert_with_key(id, &version).await?;
+        txn.font_versions.insert_with_key(id, &version)?;
 
         for version_glyph in version_glyphs {
-            self.font_version_glyphs.insert_with_key(
+            txn.font_version_glyphs.insert_with_key(
                 font::VersionGlyphKey {
                     font_version: id,
-                    char: self.get_glyph_char(version_glyph.glyph).await?,
+                    char: txn.glyphs.get(version_glyph.glyph)?.char,
                 },
-                &version_glyph,
-            ).await?;
+                version_glyph,
+            )?;
         }
 
         Ok(version)
     }
 
-    pub async fn submit_time(
+    /// Stores the candidates in the range, out of the variants of the version's glyphs, and returns their IDs. Every glyph gets the same number of variants, and there are `candidate_count` of them in total. The variants only depend on the glyphs and the version's seed, mutation and minimum candidates, so the rest of them can be made later.
+    fn add_candidates(
+        &self,
+        txn: &Txn,
+        version_id: Id<font::Version>,
+        version: &font::Version,
+        glyph_ids: &[Id<Glyph>],
+        range: Range<usize>,
+    ) -> Result<Vec<Id<Glyph>>, TransactionError> {
+        let mut glyphs = Vec::with_capacity(glyph_ids.len());
+        let mut parents = BTreeMap::new();
+        for &glyph_id in glyph_ids {
+            let glyph = txn.glyphs.get(glyph_id)?;
+            parents.insert(glyph.char, glyph_id);
+            glyphs.push(glyph);
+        }
+        // The same variants are made whatever order the glyphs are given in
+        glyphs.sort_by_key(|glyph| glyph.char);
+
+        let rng = fastrand::Rng::with_seed(version.seed);
+        let mut variants = Vec::new();
+        while !glyphs.is_empty() && variants.len() < range.end {
+            variants.extend(Glyph::generate_variants(glyphs.iter(), &version.mutation, &rng));
+        }
+
+        let mut ids = Vec::with_capacity(range.len());
+        for variant in variants.get(range).unwrap_or_default() {
+            let id = txn.glyphs.insert(variant)?;
+            // Each version has one glyph for each character, so the character identifies the parent
+            if let Some(&parent) = parents.get(&variant.char) {
+                txn.glyph_origins.insert_with_key(id, &font::GlyphOrigin {
+                    parent,
+                    font_version: version_id,
+                })?;
+            }
+            ids.push(id);
+        }
+
+        Ok(ids)
+    }
+
+    /// Records a character typed for the user's current test, which ends when all of the correct characters are typed or after `MAX_MISTAKES`.
+    pub async fn submit_keystroke(
         &self,
         user_id: Id<User>,
-        new_time: f64,
+        keystroke: Keystroke,
     ) -> Result<(), E> {
-        let test = match self.active_tests.remove(user_id).await? {
-            Some(removed_value) => removed_value,
-            None => return Ok(()),
-        };
+        self.check_time(keystroke.time)?;
+        let now = now();
+        let submitted = self.transaction(|txn| {
+            let mut test = match active_test(txn, user_id)? {
+                Some(test) if test.is_expired(now) => {
+                    self.remove_expired_test(txn, user_id, &test)?;
+                    return Ok(None);
+                },
+                Some(test) if test.kind != TestKind::MultipleChoice => test,
+                _ => return Ok(None),
+            };
+
+            let expected = match test.kind {
+                TestKind::Word => {
+                    let mut chars = Vec::with_capacity(test.options().len());
+                    for &glyph_id in test.options() {
+                        chars.push(txn.glyphs.get(glyph_id)?.char);
+                    }
+                    chars
+                },
+                _ => vec![txn.glyphs.get(test.glyph)?.char],
+            };
+
+            test.keystrokes.push(keystroke);
+            let outcome = match test.score_typing(&expected) {
+                Some(outcome) => outcome,
+                None => {
+                    txn.active_tests.insert_with_key(user_id, &test)?;
+                    return Ok(None);
+                },
+            };
+            txn.active_tests.remove(user_id)?;
+
+            self.finish_test(txn, user_id, test, outcome).map(Some)
+        }).await?;
+
+        if let Some(submitted) = submitted {
+            self.record_submission(&submitted, now);
+            self.announce_submission(&submitted).await;
+            if submitted.queue_emptied || submitted.queue_short {
+                self.worker.wake();
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the glyph chosen for the user's current `TestKind::MultipleChoice` test, which ends the test.
+    pub async fn submit_choice(
+        &self,
+        user_id: Id<User>,
+        index: usize,
+        time: f64,
+    ) -> Result<(), E> {
+        self.check_time(time)?;
+        let now = now();
+        let submitted = self.transaction(|txn| {
+            let test = match active_test(txn, user_id)? {
+                Some(test) if test.is_expired(now) => {
+                    self.remove_expired_test(txn, user_id, &test)?;
+                    return Ok(None);
+                },
+                Some(test) if test.kind == TestKind::MultipleChoice => test,
+                _ => return Ok(None),
+            };
+            txn.active_tests.remove(user_id)?;
 
-        let font = self.fonts.get(test.font).await?;
+            let chosen_char = match test.options().get(index) {
+                Some(&glyph_id) => Some(txn.glyphs.get(glyph_id)?.char),
+                None => None,
+            };
+            let outcome = test.score_choice(index, chosen_char, time);
+
+            self.finish_test(txn, user_id, test, outcome).map(Some)
+        }).await?;
 
-        let version_glyph_key = font::VersionGlyphKey {
-            font_version: font.current_version,
-            char: self.get_glyph_char(test.glyph).await?,
+        if let Some(submitted) = submitted {
+            self.record_submission(&submitted, now);
+            self.announce_submission(&submitted).await;
+            if submitted.queue_emptied || submitted.queue_short {
+                self.worker.wake();
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects times that can't come from a person, so that one request can't decide a glyph's fitness. No test lasts longer than `test_lifetime`.
+    fn check_time(&self, time: f64) -> Result<(), E> {
+        let max = self.worker.test_lifetime.as_millis() as f64;
+        if (MIN_TIME..=max).contains(&time) {
+            Ok(())
+        } else {
+            Err(E::invalid_request(&format!("time {} isn't between {} and {} milliseconds", time, MIN_TIME, max)))
+        }
+    }
+
+    fn finish_test(
+        &self,
+        txn: &Txn,
+        user_id: Id<User>,
+        test: ActiveTest,
+        outcome: Outcome,
+    ) -> Result<Submitted, TransactionError> {
+        let mut submitted = Submitted {
+            kind: test.kind,
+            served: test.served,
+            correct: outcome.time.is_some(),
+            font: test.font,
+            trials: None,
+            queue_emptied: false,
+            queue_short: false,
         };
 
-        match self.font_version_glyphs.get_option(version_glyph_key).await? {
-            Some(font::VersionGlyph {
-                score: Some(font::Score { time, .. }),
-                ..
-            }) if new_time > time => {},
-            _ => {
-                self.font_version_glyphs.insert_with_key(
-                    version_glyph_key,
-                    &font::VersionGlyph {
-                        glyph: test.glyph,
-                        score: Some(font::Score {
-                            time: new_time,
-                            user: user_id,
-                        }),
-                    },
-                ).await?;
+        let stats_key = stats::Key {
+            user: user_id,
+            font: test.font,
+        };
+        let mut tests = txn.tester_stats.get_option(stats_key)?.unwrap_or_default();
+        tests.add(outcome.time);
+        txn.tester_stats.insert_with_key(stats_key, &tests)?;
+
+        if Some(test.font) == self.fitness.reference_font {
+            // Control tests only count if the glyph was read correctly
+            if let Some(time) = outcome.time {
+                let mut baseline = txn.baselines.get_option(user_id)?.unwrap_or_default();
+                baseline.add(time);
+                txn.baselines.insert_with_key(user_id, &baseline)?;
+            }
+            return Ok(submitted);
+        }
+
+        let mut font = txn.fonts.get(test.font)?;
+        if font.current_version != test.font_version {
+            return Ok(submitted);
+        }
+
+        let trials_key = font::TrialsKey {
+            version_glyph: font::VersionGlyphKey {
+                font_version: test.font_version,
+                char: txn.glyphs.get(test.glyph)?.char,
             },
+            glyph: test.glyph,
         };
-        Ok(())
+        let mut trials = txn.trials.get_option(trials_key)?.unwrap_or_default();
+
+        match outcome.time {
+            Some(time) => {
+                let baseline = txn.baselines.get_option(user_id)?.unwrap_or_default();
+                trials.times.push(font::Trial {
+                    time,
+                    normalized_time: self.fitness.normalization.apply(time, &baseline),
+                    user: user_id,
+                });
+            },
+            None => trials.errors += 1,
+        }
+        txn.trials.insert_with_key(trials_key, &trials)?;
+        submitted.trials = Some(trials_key);
+
+        for typed in outcome.confusions {
+            let confusion_key = font::ConfusionKey {
+                trials: trials_key,
+                typed,
+            };
+            let mut confusion = txn.confusions.get_option(confusion_key)?.unwrap_or_default();
+            confusion.count += 1;
+            txn.confusions.insert_with_key(confusion_key, &confusion)?;
+        }
+
+        if trials.count() >= self.fitness.min_trials as usize {
+            font.candidates.retain(|&id| id != test.glyph);
+            txn.fonts.insert_with_key(test.font, &font)?;
+            submitted.queue_emptied = font.candidates.is_empty() && font.unqueued_candidates == 0;
+            submitted.queue_short = font.unqueued_candidates > 0 && font.candidates.len() < self.worker.queue_depth;
+        }
+
+        Ok(submitted)
     }
 
     pub async fn add_next_test(
@@ -122,67 +627,1328 @@ impl State {
         font_id: Id<Font>,
         user_id: Id<User>,
     ) -> Result<(), E> {
-        let mut font = self.fonts.get(font_id).await?;
+        if let Some(reference_font) = self.fitness.reference_font {
+            // Tests of the reference font only show its current glyphs, never its candidates
+            if font_id == reference_font {
+                return self.add_control_test(reference_font, user_id).await;
+            }
+            let baseline = self.baselines.get_option(user_id).await?.unwrap_or_default();
+            if baseline.count < self.fitness.min_controls || fastrand::f64() < self.fitness.control_rate {
+                return self.add_control_test(reference_font, user_id).await;
+            }
+        }
 
-        if font.candidates.is_empty() {
-            // Get from the current font version
-            let version_glyphs: Vec<font::VersionGlyph> = {
-                let mut stream = self.font_version_glyphs.scan_prefix(font.current_version)?;
-                let mut vec = Vec::with_capacity(stream.size_hint().0);
-                while let Some(result) = stream.next().await {
-                    vec.push(result?.1);
+        loop {
+            let font_version = self.fonts.get(font_id).await?.current_version;
+            let version_glyphs = self.get_version_glyphs(font_version).await?;
+            // A font without glyphs has nothing to test
+            if version_glyphs.is_empty() {
+                return Ok(());
+            }
+            let served = now();
+
+            let added = self.transaction(|txn| {
+                let mut font = txn.fonts.get(font_id)?;
+                if font.current_version != font_version {
+                    return Ok(None);
+                }
+                if font.candidates.is_empty() {
+                    return Ok(Some(None));
+                }
+
+                // Move the candidate to the back of the queue, so other users get other candidates while this one is being tested
+                let glyph_id = font.candidates.remove(0);
+                font.candidates.push(glyph_id);
+
+                let char = txn.glyphs.get(glyph_id)?.char;
+                let options = choose_options(font.test_kind, &version_glyphs, glyph_id, char);
+                txn.active_tests.insert_with_key(
+                    user_id,
+                    &ActiveTest::new(font_id, font.current_version, glyph_id, font.test_kind, options, served, self.worker.test_lifetime),
+                )?;
+
+                // Save the modified font
+                txn.fonts.insert_with_key(font_id, &font)?;
+                Ok(Some(Some(font.test_kind)))
+            }).await?;
+
+            match added {
+                Some(Some(kind)) => {
+                    self.metrics.tests_served.increment(&[kind.name()]);
+                    return Ok(());
+                },
+                // The worker hasn't caught up with the tests, so the queue is filled here, or the next version is started if every candidate was tested enough
+                Some(None) => {
+                    if self.fonts.get(font_id).await?.unqueued_candidates > 0 {
+                        self.fill_queue(font_id, font_version, self.worker.queue_depth).await?;
+                    } else {
+                        self.advance_font(font_id, font_version).await?;
+                    }
+                },
+                // The font moved on to another version after its glyphs were read
+                None => continue,
+            }
+        }
+    }
+
+    /// Removes every test that has expired. Called by the worker.
+    pub async fn expire_tests(&self) -> Result<(), E> {
+        let now = now();
+        let mut expired = Vec::new();
+        let mut stream = self.active_tests.iter_checked();
+        while let Some(result) = stream.next().await {
+            match result? {
+                Ok((user_id, test)) => {
+                    if test.is_expired(now) {
+                        expired.push(user_id);
+                    }
+                },
+                // Such as tests saved before an upgrade. Their candidates can't be found, so they're only removed.
+                Err(undecodable) => self.active_tests.remove_undecodable(&undecodable.key).await?,
+            }
+        }
+
+        for user_id in expired {
+            self.transaction(|txn| {
+                // The user might have started another test since the first read
+                match active_test(txn, user_id)? {
+                    Some(test) if test.is_expired(now) => self.remove_expired_test(txn, user_id, &test),
+                    _ => Ok(()),
+                }
+            }).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the test without recording a result, and handles its candidate according to `worker::Config::expiry`. If a discarded candidate was the last one in the queue, the worker's next pass starts the next version.
+    fn remove_expired_test(&self, txn: &Txn, user_id: Id<User>, test: &ActiveTest) -> Result<(), TransactionError> {
+        txn.active_tests.remove(user_id)?;
+
+        let mut font = txn.fonts.get(test.font)?;
+        // Candidates of older versions aren't tested anymore, and candidates that left the queue were tested enough
+        if font.current_version == test.font_version && font.candidates.contains(&test.glyph) {
+            font.candidates.retain(|&id| id != test.glyph);
+            if let Expiry::Requeue = self.worker.expiry {
+                font.candidates.insert(0, test.glyph);
+            }
+            txn.fonts.insert_with_key(test.font, &font)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the next version of every font whose candidates have all been tested enough. Called by the worker.
+    pub async fn advance_fonts(&self) -> Result<(), E> {
+        let mut finished = Vec::new();
+        let mut stream = self.fonts.iter();
+        while let Some(result) = stream.next().await {
+            let (font_id, font) = result?;
+            if font.candidates.is_empty() && font.unqueued_candidates == 0 && Some(font_id) != self.fitness.reference_font {
+                finished.push((font_id, font.current_version));
+            }
+        }
+
+        for (font_id, font_version) in finished {
+            self.advance_font(font_id, font_version).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the next version of the font if it's still at `font_version` and every candidate was tested enough.
+    async fn advance_font(&self, font_id: Id<Font>, font_version: Id<font::Version>) -> Result<(), E> {
+        // The glyphs with the best fitness are used in the next font version
+        let mut winners = Vec::new();
+        let mut stream = self.font_version_glyphs.scan_prefix(font_version)?;
+        while let Some(result) = stream.next().await {
+            let (key, version_glyph) = result?;
+            winners.push(self.select_winner(key, version_glyph).await?);
+        }
+
+        let advanced = self.transaction(|txn| {
+            let mut font = txn.fonts.get(font_id)?;
+            // Skip fonts that were rolled back or given new candidates since they were read
+            if font.current_version != font_version || !font.candidates.is_empty() || font.unqueued_candidates > 0 {
+                return Ok(None);
+            }
+            self.advance_version(txn, font_id, &mut font, &winners)?;
+            txn.fonts.insert_with_key(font_id, &font)?;
+            Ok(Some(font.current_version))
+        }).await?;
+        if let Some(version_id) = advanced {
+            self.metrics.generations.increment(&[&font_id.to_string()]);
+            self.announce_version(font_id, version_id).await;
+        }
+
+        Ok(())
+    }
+
+    /// Makes more candidates for every font with fewer than `worker::Config::queue_depth` of them queued, until its version's candidates run out. Called by the worker.
+    pub async fn fill_queues(&self) -> Result<(), E> {
+        let mut short = Vec::new();
+        let mut stream = self.fonts.iter();
+        while let Some(result) = stream.next().await {
+            let (font_id, font) = result?;
+            if font.unqueued_candidates > 0 && font.candidates.len() < self.worker.queue_depth && Some(font_id) != self.fitness.reference_font {
+                short.push((font_id, font.current_version));
+            }
+        }
+
+        for (font_id, font_version) in short {
+            self.fill_queue(font_id, font_version, self.worker.queue_depth).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the font's next unqueued candidates and puts them at the front of the queue, until it's `depth` long, if the font is still at `font_version`.
+    async fn fill_queue(&self, font_id: Id<Font>, font_version: Id<font::Version>, depth: usize) -> Result<(), E> {
+        let version = self.font_versions.get(font_version).await?;
+        let glyph_ids: Vec<_> = self.get_version_glyphs(font_version).await?
+            .into_iter()
+            .map(|(_, glyph_id)| glyph_id)
+            .collect();
+        let total = candidate_count(&version, glyph_ids.len());
+
+        self.transaction(|txn| {
+            let mut font = txn.fonts.get(font_id)?;
+            let count = depth.saturating_sub(font.candidates.len()).min(font.unqueued_candidates as usize);
+            if font.current_version != font_version || count == 0 {
+                return Ok(());
+            }
+            let start = total.saturating_sub(font.unqueued_candidates as usize);
+            let mut ids = self.add_candidates(txn, font_version, &version, &glyph_ids, start..start + count)?;
+            fastrand::shuffle(&mut ids);
+            font.candidates.splice(0..0, ids);
+            font.unqueued_candidates -= count as u32;
+            txn.fonts.insert_with_key(font_id, &font)
+        }).await
+    }
+
+    /// Makes the winners the font's next version, and queues new candidates for it. The font isn't saved.
+    fn advance_version(&self, txn: &Txn, font_id: Id<Font>, font: &mut Font, winners: &[font::VersionGlyph]) -> Result<(), TransactionError> {
+        let id = txn.font_versions.get(font.current_version)?.next_version;
+        let parent = font.current_version;
+        for winner in winners {
+            self.count_winning_score(txn, font_id, parent, winner)?;
+        }
+        self.start_version(txn, font, id, Some(parent), winners)
+    }
+
+    /// Adds to the tester's `stats::Tests::winning_scores` if the winner's score is new. Glyphs that stay in the next version with the same tester's score aren't counted again.
+    fn count_winning_score(
+        &self,
+        txn: &Txn,
+        font_id: Id<Font>,
+        version_id: Id<font::Version>,
+        winner: &font::VersionGlyph,
+    ) -> Result<(), TransactionError> {
+        let user_id = match &winner.score {
+            Some(score) => score.user,
+            None => return Ok(()),
+        };
+        let key = font::VersionGlyphKey {
+            font_version: version_id,
+            char: txn.glyphs.get(winner.glyph)?.char,
+        };
+        let previous = txn.font_version_glyphs.get_option(key)?;
+        let is_same = previous.is_some_and(|previous| {
+            previous.glyph == winner.glyph && previous.score.is_some_and(|score| score.user == user_id)
+        });
+        // Deleted users' statistics aren't kept
+        if is_same || txn.users.get_option(user_id)?.is_none() {
+            return Ok(());
+        }
+
+        let stats_key = stats::Key {
+            user: user_id,
+            font: font_id,
+        };
+        let mut tests = txn.tester_stats.get_option(stats_key)?.unwrap_or_default();
+        tests.winning_scores += 1;
+        txn.tester_stats.insert_with_key(stats_key, &tests)
+    }
+
+    /// Adds a version with the glyphs, makes it the font's current version, and queues new candidates for it. The font isn't saved.
+    fn start_version(
+        &self,
+        txn: &Txn,
+        font: &mut Font,
+        id: Id<font::Version>,
+        parent: Option<Id<font::Version>>,
+        version_glyphs: &[font::VersionGlyph],
+    ) -> Result<(), TransactionError> {
+        let version = self.add_font_version(txn, id, parent, version_glyphs)?;
+        font.current_version = id;
+
+        let glyph_ids: Vec<_> = version_glyphs.iter().map(|version_glyph| version_glyph.glyph).collect();
+        let total = candidate_count(&version, glyph_ids.len());
+        let queued = total.min(self.worker.queue_depth);
+        font.candidates = self.add_candidates(txn, id, &version, &glyph_ids, 0..queued)?;
+        // The glyphs themselves are compared with their variants
+        font.candidates.extend(&glyph_ids);
+        fastrand::shuffle(&mut font.candidates);
+        font.unqueued_candidates = (total - queued) as u32;
+        Ok(())
+    }
+
+    /// Abandons the versions after `version_id`, which must be in the font's history. Versions never change, so the font moves on to a new version with the same glyphs as the earlier one and new candidates. Returns the new current version.
+    pub async fn roll_back_font(
+        &self,
+        font_id: Id<Font>,
+        version_id: Id<font::Version>,
+    ) -> Result<Id<font::Version>, E> {
+        loop {
+            let font = self.fonts.get(font_id).await?;
+            if !self.is_in_history(&font, version_id).await? {
+                return Err(E::not_in_history());
+            }
+            let checked_version = font.current_version;
+
+            let version_glyphs = self.copy_version_glyphs(version_id).await?;
+            let id = self.transaction(|txn| {
+                let mut font = txn.fonts.get(font_id)?;
+                if font.current_version != checked_version {
+                    return Ok(None);
                 }
-                vec
+                let id = txn.font_versions.generate_id()?;
+                self.start_version(txn, &mut font, id, Some(version_id), &version_glyphs)?;
+                txn.fonts.insert_with_key(font_id, &font)?;
+                Ok(Some(id))
+            }).await?;
+
+            // Otherwise the font moved on while its history was read, and the version might not be in it anymore
+            if let Some(id) = id {
+                self.announce_version(font_id, id).await;
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Adds a font whose first version has the same glyphs as any existing version. The first version's parent is that version, but the new font's history starts at its first version.
+    pub async fn fork_font(
+        &self,
+        version_id: Id<font::Version>,
+        test_kind: TestKind,
+    ) -> Result<Id<Font>, E> {
+        // The version might be garbage, so garbage isn't deleted until the fork is saved
+        let mut forks = self.forks.lock().await;
+        *forks += 1;
+        // Unknown versions would otherwise become forks without glyphs
+        self.font_versions.get(version_id).await?;
+        let version_glyphs = self.copy_version_glyphs(version_id).await?;
+        self.transaction(|txn| {
+            let first_version_id = txn.font_versions.generate_id()?;
+            let mut font = Font {
+                first_version: first_version_id,
+                current_version: first_version_id,
+                test_kind,
+                candidates: Vec::new(),
+                unqueued_candidates: 0,
             };
+            self.start_version(txn, &mut font, first_version_id, Some(version_id), &version_glyphs)?;
+            txn.fonts.insert(&font)
+        }).await
+    }
 
-            // Duplicate the current font version, with the same `font::VersionGlyph`s being included
-            font.current_version = {
-                let id = self.font_versions.get(font.current_version).await?.next_version;
-                let _: font::Version = self.add_font_version(id, version_glyphs.iter()).await?;
-                id
+    async fn is_in_history(&self, font: &Font, version_id: Id<font::Version>) -> Result<bool, E> {
+        let mut next = Some(font.current_version);
+        while let Some(id) = next {
+            if id == version_id {
+                return Ok(true);
+            }
+            next = if id == font.first_version {
+                None
+            } else {
+                self.font_versions.get(id).await?.parent
             };
+        }
+        Ok(false)
+    }
 
-            // Add candidates
-            font.candidates = {
-                let mut glyphs = Vec::with_capacity(version_glyphs.len());
-                for font::VersionGlyph { glyph, .. } in version_glyphs {
-                    glyphs.push(
-                        self.glyphs.get(glyph).await?
-                    );
+    /// Returns each `font::VersionGlyph` in the version, including its score.
+    async fn copy_version_glyphs(
+        &self,
+        font_version: Id<font::Version>,
+    ) -> Result<Vec<font::VersionGlyph>, E> {
+        let mut stream = self.font_version_glyphs.scan_prefix(font_version)?;
+        let mut vec = Vec::with_capacity(stream.size_hint().0);
+        while let Some(result) = stream.next().await {
+            vec.push(result?.1);
+        }
+        Ok(vec)
+    }
+
+    /// Runs `generations` versions without human testers, choosing each winner with `evaluator::Config` instead of trials. Templates come from the reference font, if there is one. The last version's candidates are left in the queue for humans to rank. Returns the new current version.
+    pub async fn evolve_offline(&self, font_id: Id<Font>, generations: u32) -> Result<Id<font::Version>, E> {
+        if Some(font_id) == self.fitness.reference_font {
+            return Err(E::invalid_request("the reference font doesn't evolve"));
+        }
+        if generations > self.evaluator.max_generations {
+            return Err(E::invalid_request(&format!("at most {} generations can be run at once", self.evaluator.max_generations)));
+        }
+        let mut templates = BTreeMap::new();
+        if let Some(reference_font) = self.fitness.reference_font {
+            let font_version = self.fonts.get(reference_font).await?.current_version;
+            for (char, glyph_id) in self.get_version_glyphs(font_version).await? {
+                templates.insert(char, self.evaluator.rasterize(&self.glyphs.get(glyph_id).await?));
+            }
+        }
+
+        let mut generation = 0;
+        while generation < generations {
+            // Every candidate of the version competes, including the ones that weren't queued yet
+            let font_version = self.fonts.get(font_id).await?.current_version;
+            self.fill_queue(font_id, font_version, usize::MAX).await?;
+            let font = self.fonts.get(font_id).await?;
+
+            let mut current = BTreeMap::new();
+            let mut stream = self.font_version_glyphs.scan_prefix(font.current_version)?;
+            while let Some(result) = stream.next().await {
+                let (key, version_glyph) = result?;
+                let bitmap = self.evaluator.rasterize(&self.glyphs.get(version_glyph.glyph).await?);
+                current.insert(key.char, (version_glyph, bitmap));
+            }
+
+            // Each candidate competes with the current glyph for its character, which wins ties
+            let mut best: BTreeMap<char, (f64, Id<Glyph>)> = BTreeMap::new();
+            for &glyph_id in &font.candidates {
+                let glyph = self.glyphs.get(glyph_id).await?;
+                let others = current
+                    .iter()
+                    .filter(|&(&char, _)| char != glyph.char)
+                    .map(|(_, (_, bitmap))| bitmap);
+                let evaluation = self.evaluator.evaluate(&self.evaluator.rasterize(&glyph), others, templates.get(&glyph.char));
+                let fitness = self.evaluator.fitness(&evaluation);
+
+                let is_current = current.get(&glyph.char).map(|(version_glyph, _)| version_glyph.glyph) == Some(glyph_id);
+                let beats_best = match best.get(&glyph.char) {
+                    Some(&(best_fitness, _)) => fitness < best_fitness || (is_current && fitness <= best_fitness),
+                    None => true,
+                };
+                if beats_best {
+                    best.insert(glyph.char, (fitness, glyph_id));
                 }
-                self.glyphs.insert_each(
-                    Glyph::generate_variants(glyphs.iter()).iter()
-                ).await?
-            };
+            }
 
-            // Save the modified font
-            self.fonts.insert_with_key(font_id, &font).await?;
+            // Glyphs chosen this way don't have a `font::Score`, because no one typed them
+            let winners: Vec<_> = current
+                .into_iter()
+                .map(|(char, (version_glyph, _))| match best.remove(&char) {
+                    Some((_, glyph_id)) if glyph_id != version_glyph.glyph => font::VersionGlyph {
+                        glyph: glyph_id,
+                        score: None,
+                    },
+                    _ => version_glyph,
+                })
+                .collect();
+
+            let advanced = self.transaction(|txn| {
+                let mut latest = txn.fonts.get(font_id)?;
+                if latest.current_version != font.current_version {
+                    return Ok(None);
+                }
+                self.advance_version(txn, font_id, &mut latest, &winners)?;
+                txn.fonts.insert_with_key(font_id, &latest)?;
+                Ok(Some(latest.current_version))
+            }).await?;
+            // Otherwise the generation is run again with the version that replaced the one that was read
+            if let Some(version_id) = advanced {
+                self.metrics.generations.increment(&[&font_id.to_string()]);
+                self.announce_version(font_id, version_id).await;
+                generation += 1;
+            }
         }
 
-        if let Some(&glyph_id) = font.candidates.first() {
+        Ok(self.fonts.get(font_id).await?.current_version)
+    }
+
+    /// Shows a random glyph from the current version of the reference font, which is never changed by tests.
+    async fn add_control_test(
+        &self,
+        reference_font: Id<Font>,
+        user_id: Id<User>,
+    ) -> Result<(), E> {
+        let font_version = self.fonts.get(reference_font).await?.current_version;
+        let glyphs = self.get_version_glyphs(font_version).await?;
+
+        if !glyphs.is_empty() {
+            let (_, glyph) = glyphs[fastrand::usize(..glyphs.len())];
             self.active_tests.insert_with_key(
                 user_id,
-                &ActiveTest {
-                    font: font_id,
-                    glyph: glyph_id,
-                },
+                &ActiveTest::new(reference_font, font_version, glyph, TestKind::Single, Vec::new(), now(), self.worker.test_lifetime),
             ).await?;
+            self.metrics.tests_served.increment(&[TestKind::Single.name()]);
         }
 
         Ok(())
     }
 
-    pub async fn get_test_glyph(
+    /// Returns the character and glyph of each `font::VersionGlyph` in the version.
+    async fn get_version_glyphs(
+        &self,
+        font_version: Id<font::Version>,
+    ) -> Result<Vec<(char, Id<Glyph>)>, E> {
+        let mut stream = self.font_version_glyphs.scan_prefix(font_version)?;
+        let mut vec = Vec::with_capacity(stream.size_hint().0);
+        while let Some(result) = stream.next().await {
+            let (key, version_glyph) = result?;
+            vec.push((key.char, version_glyph.glyph));
+        }
+        Ok(vec)
+    }
+
+    /// Returns the glyph with the best fitness, or `current` if none of the glyphs have enough trials.
+    async fn select_winner(
+        &self,
+        key: font::VersionGlyphKey,
+        current: font::VersionGlyph,
+    ) -> Result<font::VersionGlyph, E> {
+        let mut winner = current;
+        let mut best_fitness = f64::INFINITY;
+
+        let mut stream = self.trials.scan_prefix(key)?;
+        while let Some(result) = stream.next().await {
+            let (trials_key, trials) = result?;
+            let top_confusion = {
+                let mut stream = self.confusions.scan_prefix(trials_key)?;
+                let mut max = 0;
+                while let Some(result) = stream.next().await {
+                    max = std::cmp::max(max, result?.1.count);
+                }
+                max
+            };
+            let fitness = self.fitness.fitness(&trials, top_confusion);
+            if let (true, Some(fastest)) = (fitness < best_fitness, trials.fastest()) {
+                best_fitness = fitness;
+                winner = font::VersionGlyph {
+                    glyph: trials_key.glyph,
+                    score: Some(font::Score {
+                        fitness,
+                        user: fastest.user,
+                    }),
+                };
+            }
+        }
+
+        Ok(winner)
+    }
+
+    pub async fn confusion_matrix(
+        &self,
+        version_id: Id<font::Version>,
+    ) -> Result<ConfusionMatrix, E> {
+        let mut counts = Vec::new();
+
+        let mut stream = self.trials.scan_prefix(version_id)?;
+        while let Some(result) = stream.next().await {
+            let (key, trials) = result?;
+            let char = key.version_glyph.char;
+            counts.push((char, char, trials.times.len() as u32));
+        }
+
+        let mut stream = self.confusions.scan_prefix(version_id)?;
+        while let Some(result) = stream.next().await {
+            let (key, confusion) = result?;
+            counts.push((key.trials.version_glyph.char, key.typed, confusion.count));
+        }
+
+        Ok(ConfusionMatrix::from_counts(counts.into_iter()))
+    }
+
+    /// Returns every version of the font, from the current version back to the first.
+    pub async fn font_history(&self, font_id: Id<Font>) -> Result<Vec<VersionSummary>, E> {
+        let font = self.fonts.get(font_id).await?;
+        let mut history = Vec::new();
+        let mut next = Some(font.current_version);
+
+        while let Some(version_id) = next {
+            let version = self.font_versions.get(version_id).await?;
+            let mut glyphs = Vec::new();
+            let mut stream = self.font_version_glyphs.scan_prefix(version_id)?;
+            while let Some(result) = stream.next().await {
+                let (key, version_glyph) = result?;
+                glyphs.push(VersionGlyphSummary {
+                    char: key.char,
+                    glyph: version_glyph.glyph.into(),
+                    fitness: version_glyph.score.map(|score| score.fitness),
+                });
+            }
+
+            history.push(VersionSummary {
+                id: version_id.into(),
+                parent: version.parent.map(Into::into),
+                created: version.created,
+                seed: version.seed.to_string(),
+                mutation: version.mutation,
+                min_candidates: version.min_candidates,
+                glyphs,
+            });
+            // A forked font's first version has a parent in another font
+            next = if version_id == font.first_version {
+                None
+            } else {
+                version.parent
+            };
+        }
+
+        Ok(history)
+    }
+
+    /// Returns the glyph followed by the glyphs it was mutated from, back to a glyph that was added with a font.
+    pub async fn glyph_ancestry(&self, glyph_id: Id<Glyph>) -> Result<Vec<Ancestor>, E> {
+        let mut ancestry = Vec::new();
+        let mut next = Some(glyph_id);
+
+        while let Some(glyph_id) = next {
+            let origin = self.glyph_origins.get_option(glyph_id).await?;
+            ancestry.push(Ancestor {
+                glyph: glyph_id.into(),
+                char: self.get_glyph_char(glyph_id).await?,
+                font_version: origin.as_ref().map(|origin| origin.font_version.into()),
+            });
+            next = origin.map(|origin| origin.parent);
+        }
+
+        Ok(ancestry)
+    }
+
+    /// Lists the characters whose glyph or score is different in `new_version`.
+    pub async fn version_diff(
+        &self,
+        old_version: Id<font::Version>,
+        new_version: Id<font::Version>,
+    ) -> Result<VersionDiff, E> {
+        // Unknown versions would otherwise look like versions without glyphs
+        self.font_versions.get(old_version).await?;
+        self.font_versions.get(new_version).await?;
+        let mut version_glyphs: BTreeMap<char, [Option<font::VersionGlyph>; 2]> = BTreeMap::new();
+        for (side, version_id) in [old_version, new_version].into_iter().enumerate() {
+            let mut stream = self.font_version_glyphs.scan_prefix(version_id)?;
+            while let Some(result) = stream.next().await {
+                let (key, version_glyph) = result?;
+                version_glyphs.entry(key.char).or_default()[side] = Some(version_glyph);
+            }
+        }
+
+        let mut chars = Vec::new();
+        for (char, [old, new]) in version_glyphs {
+            let fitness = |version_glyph: &font::VersionGlyph| version_glyph.score.as_ref().map(|score| score.fitness);
+            if let (Some(old), Some(new)) = (&old, &new) {
+                if old.glyph == new.glyph && fitness(old) == fitness(new) {
+                    continue;
+                }
+            }
+
+            let mut glyphs = [None, None];
+            let mut sides = [None, None];
+            for (index, version_glyph) in [old, new].into_iter().enumerate() {
+                if let Some(version_glyph) = version_glyph {
+                    let glyph = self.glyphs.get(version_glyph.glyph).await?;
+                    sides[index] = Some(DiffSide {
+                        glyph: version_glyph.glyph.into(),
+                        fitness: fitness(&version_glyph),
+                        outline: glyph.to_svg_path_d(),
+                    });
+                    glyphs[index] = Some(glyph);
+                }
+            }
+
+            let (points, shape_changed) = match glyphs {
+                [Some(old), Some(new)] => diff::compare_glyphs(&old, &new),
+                _ => (Vec::new(), true),
+            };
+            let [old, new] = sides;
+            chars.push(CharDiff {
+                char,
+                old,
+                new,
+                points,
+                shape_changed,
+            });
+        }
+
+        Ok(VersionDiff {
+            old_version: old_version.into(),
+            new_version: new_version.into(),
+            chars,
+        })
+    }
+
+    /// Ranks testers by winning scores, then by finished tests, in one font or in every font. Deleted users are left out.
+    pub async fn leaderboard(&self, font_id: Option<Id<Font>>, limit: usize) -> Result<Leaderboard, E> {
+        if let Some(font_id) = font_id {
+            self.fonts.get(font_id).await?;
+        }
+        let mut tests: BTreeMap<Id<User>, stats::Tests> = BTreeMap::new();
+        let mut stream = self.tester_stats.iter();
+        while let Some(result) = stream.next().await {
+            let (key, font_tests) = result?;
+            if font_id.is_none_or(|font_id| key.font == font_id) {
+                tests.entry(key.user).or_default().merge(&font_tests);
+            }
+        }
+
+        let mut entries = Vec::new();
+        for (user_id, tests) in tests {
+            if let Some(user) = self.users.get_option(user_id).await? {
+                entries.push(LeaderboardEntry {
+                    user: user_id.into(),
+                    name: user.name().into_owned(),
+                    stats: tests.summary(),
+                });
+            }
+        }
+        // The sort is stable, so ties stay in the order of user IDs
+        entries.sort_by(|a, b| {
+            b.stats.winning_scores.cmp(&a.stats.winning_scores).then(b.stats.tests.cmp(&a.stats.tests))
+        });
+        entries.truncate(limit);
+
+        Ok(Leaderboard {
+            font: font_id.map(Into::into),
+            entries,
+        })
+    }
+
+    /// Returns the user's results in every font, and in each font where they finished a test or set a winning score.
+    pub async fn user_stats(&self, user_id: Id<User>) -> Result<UserStats, E> {
+        let user = self.users.get(user_id).await?;
+
+        let mut overall = stats::Tests::default();
+        let mut fonts = Vec::new();
+        let mut stream = self.tester_stats.scan_prefix(user_id)?;
+        while let Some(result) = stream.next().await {
+            let (key, tests) = result?;
+            overall.merge(&tests);
+            fonts.push(FontStats {
+                font: key.font.into(),
+                stats: tests.summary(),
+            });
+        }
+
+        Ok(UserStats {
+            user: user_id.into(),
+            name: user.name().into_owned(),
+            overall: overall.summary(),
+            fonts,
+        })
+    }
+
+    pub async fn get_test(
         &self,
         user_id: Id<User>,
-    ) -> Result<Option<Glyph>, E> {
-        Ok(match self.active_tests.get_option(user_id).await? {
-            Some(test) => self.glyphs.get_option(test.glyph).await?,
-            None => None,
+    ) -> Result<Option<Presentation>, E> {
+        let test = match self.active_tests.get_option(user_id).await {
+            Ok(Some(test)) if !test.is_expired(now()) => test,
+            // Removed by the worker soon, like tests that can't be decoded
+            Ok(_) | Err(E::Decode(_)) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let glyph = self.glyphs.get(test.glyph).await?.to_svg_path_d();
+
+        let mut options = Vec::with_capacity(test.options().len());
+        for &glyph_id in test.options() {
+            options.push(self.glyphs.get(glyph_id).await?.to_svg_path_d());
+        }
+
+        Ok(Some(match test.kind {
+            TestKind::Single => Presentation::Single {
+                glyph,
+            },
+            TestKind::MultipleChoice => Presentation::MultipleChoice {
+                char: self.get_glyph_char(test.glyph).await?,
+                glyphs: options,
+            },
+            TestKind::Word => Presentation::Word {
+                glyphs: options,
+            },
+            TestKind::Blurred => Presentation::Blurred {
+                glyph,
+                blur: BLUR,
+            },
+            TestKind::Small => Presentation::Small {
+                glyph,
+                size: SMALL_SIZE,
+            },
+        }))
+    }
+
+    /// Times from different kinds of tests can't be compared, so unless the kind stays the same, the font moves on to a new version with the current version's glyphs and new candidates.
+    pub async fn set_test_kind(&self, font_id: Id<Font>, test_kind: TestKind) -> Result<(), E> {
+        loop {
+            let font = self.fonts.get(font_id).await?;
+            if font.test_kind == test_kind {
+                return Ok(());
+            }
+            let parent = font.current_version;
+            let version_glyphs = self.copy_version_glyphs(parent).await?;
+
+            let id = self.transaction(|txn| {
+                let mut font = txn.fonts.get(font_id)?;
+                if font.current_version != parent {
+                    return Ok(None);
+                }
+                font.test_kind = test_kind;
+                let id = txn.font_versions.generate_id()?;
+                self.start_version(txn, &mut font, id, Some(parent), &version_glyphs)?;
+                txn.fonts.insert_with_key(font_id, &font)?;
+                Ok(Some(id))
+            }).await?;
+
+            // Otherwise the font moved on while the glyphs were read
+            if let Some(id) = id {
+                self.announce_version(font_id, id).await;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Deletes the garbage from `find_garbage`, or only counts it with `dry_run`. If a font is forked while it's deleted, the garbage is found again.
+    pub async fn collect_garbage(&self, dry_run: bool) -> Result<GarbageReport, E> {
+        let mut report = GarbageReport {
+            dry_run,
+            ..GarbageReport::default()
+        };
+        loop {
+            let garbage = self.find_garbage().await?;
+            if self.delete_garbage(&garbage, &mut report).await? {
+                return Ok(report);
+            }
+        }
+    }
+
+    /// Finds versions that aren't in the history of any font, and glyphs that can't be reached from a remaining version, a font's queue, an active test or the trials of a remaining version. Glyphs that a remaining glyph was mutated from are kept, so ancestry stays complete. Glyphs that weren't mutated from another glyph are kept too, because they were imported to make fonts from.
+    pub async fn find_garbage(&self) -> Result<Garbage, E> {
+        let forks = *self.forks.lock().await;
+        // Anything added after this ID might be referenced by changes made while marking, so it's kept
+        let watermark = u64::from(Id::generate(&self.glyphs).await?);
+
+        let mut versions = BTreeSet::new();
+        let mut glyphs = BTreeSet::new();
+        let mut stream = self.fonts.iter();
+        while let Some(result) = stream.next().await {
+            let (_, font) = result?;
+            glyphs.extend(font.candidates.iter().copied());
+
+            // Forks keep the history of the font they came from
+            let mut next = Some(font.current_version);
+            while let Some(version_id) = next.filter(|&id| versions.insert(id)) {
+                next = self.font_versions.get(version_id).await?.parent;
+                let mut version_glyphs = self.font_version_glyphs.scan_prefix(version_id)?;
+                while let Some(result) = version_glyphs.next().await {
+                    glyphs.insert(result?.1.glyph);
+                }
+                // Trials are kept with their version, including trials of candidates that lost or left the queue
+                let mut trials = self.trials.scan_prefix(version_id)?;
+                while let Some(result) = trials.next().await {
+                    glyphs.insert(result?.0.glyph);
+                }
+            }
+        }
+
+        let mut stream = self.active_tests.iter();
+        while let Some(result) = stream.next().await {
+            let (_, test) = result?;
+            glyphs.insert(test.glyph);
+            glyphs.extend(test.options().iter().copied());
+        }
+
+        let mut unvisited: Vec<Id<Glyph>> = glyphs.iter().copied().collect();
+        while let Some(glyph_id) = unvisited.pop() {
+            if let Some(origin) = self.glyph_origins.get_option(glyph_id).await? {
+                if glyphs.insert(origin.parent) {
+                    unvisited.push(origin.parent);
+                }
+            }
+        }
+
+        let mut garbage_versions = Vec::new();
+        let mut stream = self.font_versions.keys();
+        while let Some(result) = stream.next().await {
+            let id = result?;
+            if u64::from(id) < watermark && !versions.contains(&id) {
+                garbage_versions.push(id);
+            }
+        }
+
+        let mut garbage_glyphs = Vec::new();
+        let mut stream = self.glyphs.keys();
+        while let Some(result) = stream.next().await {
+            let id = result?;
+            if u64::from(id) < watermark && !glyphs.contains(&id) && self.glyph_origins.get_option(id).await?.is_some() {
+                garbage_glyphs.push(id);
+            }
+        }
+
+        Ok(Garbage {
+            versions: garbage_versions,
+            glyphs: garbage_glyphs,
+            forks,
         })
     }
 
+    /// Deletes the garbage in transactions of `GC_BATCH_SIZE` items, so tests can continue meanwhile, and adds what was deleted to the report. With `GarbageReport::dry_run`, it's only counted. Returns `false` before deleting everything if a font was forked since the garbage was found, because the fork could use any of it.
+    pub async fn delete_garbage(&self, garbage: &Garbage, report: &mut GarbageReport) -> Result<bool, E> {
+        let dry_run = report.dry_run;
+        for batch in garbage.versions.chunks(GC_BATCH_SIZE) {
+            let mut version_glyphs = Vec::new();
+            let mut trials = Vec::new();
+            let mut confusions = Vec::new();
+            for &version_id in batch {
+                let mut stream = self.font_version_glyphs.scan_prefix(version_id)?;
+                while let Some(result) = stream.next().await {
+                    version_glyphs.push(result?.0);
+                }
+                let mut stream = self.trials.scan_prefix(version_id)?;
+                while let Some(result) = stream.next().await {
+                    trials.push(result?.0);
+                }
+                let mut stream = self.confusions.scan_prefix(version_id)?;
+                while let Some(result) = stream.next().await {
+                    confusions.push(result?.0);
+                }
+            }
+            if !dry_run {
+                let forks = self.forks.lock().await;
+                let deleted = self.transaction(|txn| {
+                    // Checked while forks are locked out, so the versions are still unreachable when they're deleted
+                    if *forks != garbage.forks {
+                        return Ok(false);
+                    }
+                    for &id in batch {
+                        txn.font_versions.remove(id)?;
+                    }
+                    for &key in &version_glyphs {
+                        txn.font_version_glyphs.remove(key)?;
+                    }
+                    for &key in &trials {
+                        txn.trials.remove(key)?;
+                    }
+                    for &key in &confusions {
+                        txn.confusions.remove(key)?;
+                    }
+                    Ok(true)
+                }).await?;
+                drop(forks);
+                if !deleted {
+                    return Ok(false);
+                }
+                async_std::task::yield_now().await;
+            }
+            report.versions += batch.len() as u64;
+            report.records += (version_glyphs.len() + trials.len() + confusions.len()) as u64;
+        }
+
+        for batch in garbage.glyphs.chunks(GC_BATCH_SIZE) {
+            // Every garbage glyph has an origin
+            if !dry_run {
+                let forks = self.forks.lock().await;
+                let deleted = self.transaction(|txn| {
+                    if *forks != garbage.forks {
+                        return Ok(false);
+                    }
+                    for &id in batch {
+                        txn.glyphs.remove(id)?;
+                        txn.glyph_origins.remove(id)?;
+                    }
+                    Ok(true)
+                }).await?;
+                drop(forks);
+                if !deleted {
+                    return Ok(false);
+                }
+                async_std::task::yield_now().await;
+            }
+            report.glyphs += batch.len() as u64;
+            report.records += batch.len() as u64;
+        }
+
+        Ok(true)
+    }
+
+    /// An archive of the whole database, for `Database::restore`
+    pub async fn backup(&self) -> Result<Vec<u8>, E> {
+        self.db.backup().await
+    }
+
+    pub async fn list_fonts(&self) -> Result<Vec<(Id<Font>, Font)>, E> {
+        let mut fonts = Vec::new();
+        let mut stream = self.fonts.iter();
+        while let Some(result) = stream.next().await {
+            fonts.push(result?);
+        }
+        Ok(fonts)
+    }
+
+    pub async fn get_glyph(&self, glyph_id: Id<Glyph>) -> Result<Glyph, E> {
+        self.glyphs.get(glyph_id).await
+    }
+
+    /// Lists the glyphs that are queued or were tested in the font's current version, ordered by ID.
+    pub async fn font_candidates(&self, font_id: Id<Font>) -> Result<Vec<CandidateSummary>, E> {
+        let font = self.fonts.get(font_id).await?;
+        let mut trials: BTreeMap<Id<Glyph>, (u32, u32, Option<f64>)> = font.candidates
+            .iter()
+            .map(|&glyph_id| (glyph_id, (0, 0, None)))
+            .collect();
+        let mut stream = self.trials.scan_prefix(font.current_version)?;
+        while let Some(result) = stream.next().await {
+            let (key, glyph_trials) = result?;
+            trials.insert(key.glyph, (glyph_trials.times.len() as u32, glyph_trials.errors, glyph_trials.median()));
+        }
+
+        let mut candidates = Vec::with_capacity(trials.len());
+        for (glyph_id, (trial_count, errors, median_time)) in trials {
+            candidates.push(CandidateSummary {
+                glyph: glyph_id.into(),
+                char: self.get_glyph_char(glyph_id).await?,
+                parent: self.glyph_origins.get_option(glyph_id).await?.map(|origin| origin.parent.into()),
+                queued: font.candidates.contains(&glyph_id),
+                trials: trial_count,
+                errors,
+                median_time,
+            });
+        }
+
+        Ok(candidates)
+    }
+
+    /// Deletes the trials and confusions of the font's current version, and queues every candidate that was tested again. Returns the number of candidates that were queued again.
+    pub async fn reset_scores(&self, font_id: Id<Font>) -> Result<usize, E> {
+        loop {
+            let font_version = self.fonts.get(font_id).await?.current_version;
+            // The keys of both trees start with the version
+            let mut trials_keys = Vec::new();
+            let mut stream = self.trials.scan_prefix(font_version)?;
+            while let Some(result) = stream.next().await {
+                trials_keys.push(result?.0);
+            }
+            let mut confusion_keys = Vec::new();
+            let mut stream = self.confusions.scan_prefix(font_version)?;
+            while let Some(result) = stream.next().await {
+                confusion_keys.push(result?.0);
+            }
+
+            let requeued = self.transaction(|txn| {
+                let mut font = txn.fonts.get(font_id)?;
+                if font.current_version != font_version {
+                    return Ok(None);
+                }
+                let mut requeued = 0;
+                for &key in &trials_keys {
+                    txn.trials.remove(key)?;
+                    if !font.candidates.contains(&key.glyph) {
+                        font.candidates.push(key.glyph);
+                        requeued += 1;
+                    }
+                }
+                for &key in &confusion_keys {
+                    txn.confusions.remove(key)?;
+                }
+                fastrand::shuffle(&mut font.candidates);
+                txn.fonts.insert_with_key(font_id, &font)?;
+                Ok(Some(requeued))
+            }).await?;
+
+            // Otherwise the font moved on while the keys were read
+            if let Some(requeued) = requeued {
+                return Ok(requeued);
+            }
+        }
+    }
+
+    /// Deletes the user with their test, baseline and statistics. Trials and scores keep the user's ID.
+    pub async fn delete_user(&self, name: &str) -> Result<(), E> {
+        let (user_id, _) = self.find_user(name).await?.ok_or_else(|| E::no_such_user(name))?;
+        let mut stats_keys = Vec::new();
+        let mut stream = self.tester_stats.scan_prefix(user_id)?;
+        while let Some(result) = stream.next().await {
+            stats_keys.push(result?.0);
+        }
+
+        self.transaction(|txn| {
+            if let Some(test) = active_test(txn, user_id)? {
+                self.remove_expired_test(txn, user_id, &test)?;
+            }
+            txn.baselines.remove(user_id)?;
+            for &key in &stats_keys {
+                txn.tester_stats.remove(key)?;
+            }
+            txn.users.remove(user_id)?;
+            txn.password_params.remove(user_id)?;
+            Ok(())
+        }).await
+    }
+
+    /// Reads every item of every tree, and reports items that can't be decoded, references to items that don't exist, and glyphs used for the wrong character. References to users from trials and scores aren't checked, because `delete_user` keeps them.
+    ///
+    /// With `repair`, items that can't be decoded are removed, and so are trials, confusions, origins, tests and baselines that refer to something missing. Fonts lose queued candidates that don't exist. Other problems with fonts, versions and their glyphs are only reported, because fixing them would lose history.
+    pub async fn verify(&self, repair: bool) -> Result<IntegrityReport, E> {
+        let mut report = IntegrityReport {
+            repair,
+            ..IntegrityReport::default()
+        };
+
+        let mut users = BTreeSet::new();
+        read_checked("users", &self.users, &mut report, |id, _| {
+            users.insert(id);
+        }).await?;
+        let wrong_entries = self.users.check_indexes(repair).await?;
+        if wrong_entries > 0 {
+            add_problem(&mut report, "users_by_name", "index".to_owned(), &format!("{} entries are missing or don't belong to a user", wrong_entries), true);
+        }
+
+        let mut glyphs = BTreeMap::new();
+        read_checked("glyphs", &self.glyphs, &mut report, |id, glyph| {
+            glyphs.insert(id, glyph.char);
+        }).await?;
+
+        let mut versions = BTreeMap::new();
+        read_checked("font_versions", &self.font_versions, &mut report, |id, version| {
+            versions.insert(id, version.parent);
+        }).await?;
+        for (&id, &parent) in &versions {
+            if parent.is_some_and(|parent| !versions.contains_key(&parent)) {
+                add_problem(&mut report, "font_versions", format!("version {}", id), "its parent doesn't exist", false);
+            }
+        }
+
+        let mut fonts = Vec::new();
+        read_checked("fonts", &self.fonts, &mut report, |id, font| fonts.push((id, font))).await?;
+        let font_ids: BTreeSet<_> = fonts.iter().map(|&(id, _)| id).collect();
+        for (font_id, font) in fonts {
+            let item = format!("font {}", font_id);
+            if !versions.contains_key(&font.first_version) {
+                add_problem(&mut report, "fonts", item.clone(), "its first version doesn't exist", false);
+            }
+            if !versions.contains_key(&font.current_version) {
+                add_problem(&mut report, "fonts", item.clone(), "its current version doesn't exist", false);
+            }
+            let missing = font.candidates.iter().filter(|id| !glyphs.contains_key(id)).count();
+            if missing > 0 && add_problem(&mut report, "fonts", item, &format!("{} queued candidates don't exist", missing), true) {
+                self.transaction(|txn| {
+                    let mut font = txn.fonts.get(font_id)?;
+                    font.candidates.retain(|id| glyphs.contains_key(id));
+                    txn.fonts.insert_with_key(font_id, &font)
+                }).await?;
+            }
+        }
+
+        let mut version_glyphs = Vec::new();
+        read_checked("scores", &self.font_version_glyphs, &mut report, |key, version_glyph| version_glyphs.push((key, version_glyph))).await?;
+        for (key, version_glyph) in version_glyphs {
+            let item = format!("{:?} in version {}", key.char, key.font_version);
+            if !versions.contains_key(&key.font_version) {
+                if add_problem(&mut report, "scores", item, "its version doesn't exist", true) {
+                    self.font_version_glyphs.remove(key).await?;
+                }
+                continue;
+            }
+            match glyphs.get(&version_glyph.glyph) {
+                None => add_problem(&mut report, "scores", item, &format!("its glyph {} doesn't exist", version_glyph.glyph), false),
+                Some(&char) if char != key.char => add_problem(&mut report, "scores", item, &format!("its glyph {} is for {:?}", version_glyph.glyph, char), false),
+                Some(_) => false,
+            };
+        }
+
+        let mut origins = Vec::new();
+        read_checked("glyph_origins", &self.glyph_origins, &mut report, |glyph_id, origin| origins.push((glyph_id, origin))).await?;
+        for (glyph_id, origin) in origins {
+            let item = format!("glyph {}", glyph_id);
+            let problem = if !glyphs.contains_key(&glyph_id) {
+                "the glyph doesn't exist"
+            } else if !glyphs.contains_key(&origin.parent) {
+                "its parent doesn't exist"
+            } else {
+                // The parent is still known, so the origin is kept
+                if !versions.contains_key(&origin.font_version) {
+                    add_problem(&mut report, "glyph_origins", item, "its version doesn't exist", false);
+                }
+                continue;
+            };
+            if add_problem(&mut report, "glyph_origins", item, problem, true) {
+                self.glyph_origins.remove(glyph_id).await?;
+            }
+        }
+
+        let mut trials_keys = Vec::new();
+        read_checked("trials", &self.trials, &mut report, |key, _| trials_keys.push(key)).await?;
+        let mut valid_trials = BTreeSet::new();
+        for key in trials_keys {
+            let version_glyph = key.version_glyph;
+            let problem = match glyphs.get(&key.glyph) {
+                _ if !versions.contains_key(&version_glyph.font_version) => "its version doesn't exist".to_owned(),
+                None => "the glyph doesn't exist".to_owned(),
+                Some(&char) if char != version_glyph.char => format!("the glyph is for {:?}", char),
+                Some(_) => {
+                    valid_trials.insert((version_glyph.font_version, version_glyph.char, key.glyph));
+                    continue;
+                },
+            };
+            let item = format!("glyph {} for {:?} in version {}", key.glyph, version_glyph.char, version_glyph.font_version);
+            if add_problem(&mut report, "trials", item, &problem, true) {
+                self.trials.remove(key).await?;
+            }
+        }
+
+        let mut confusion_keys = Vec::new();
+        read_checked("confusions", &self.confusions, &mut report, |key, _| confusion_keys.push(key)).await?;
+        for key in confusion_keys {
+            let version_glyph = key.trials.version_glyph;
+            if !valid_trials.contains(&(version_glyph.font_version, version_glyph.char, key.trials.glyph)) {
+                let item = format!("{:?} typed for glyph {} in version {}", key.typed, key.trials.glyph, version_glyph.font_version);
+                if add_problem(&mut report, "confusions", item, "its trials don't exist", true) {
+                    self.confusions.remove(key).await?;
+                }
+            }
+        }
+
+        let mut tests = Vec::new();
+        read_checked("test_sessions", &self.active_tests, &mut report, |user_id, test| tests.push((user_id, test))).await?;
+        for (user_id, test) in tests {
+            let problem = if !users.contains(&user_id) {
+                "the user doesn't exist"
+            } else if !versions.contains_key(&test.font_version) {
+                "its version doesn't exist"
+            } else if !glyphs.contains_key(&test.glyph) || !test.options().iter().all(|id| glyphs.contains_key(id)) {
+                "a glyph that it shows doesn't exist"
+            } else {
+                continue;
+            };
+            if add_problem(&mut report, "test_sessions", format!("user {}", user_id), problem, true) {
+                self.active_tests.remove(user_id).await?;
+            }
+        }
+
+        let mut baselines = Vec::new();
+        read_checked("baselines", &self.baselines, &mut report, |user_id, _| baselines.push(user_id)).await?;
+        for user_id in baselines {
+            if !users.contains(&user_id) && add_problem(&mut report, "baselines", format!("user {}", user_id), "the user doesn't exist", true) {
+                self.baselines.remove(user_id).await?;
+            }
+        }
+
+        let mut password_params = Vec::new();
+        read_checked("password_params", &self.password_params, &mut report, |user_id, _| password_params.push(user_id)).await?;
+        for user_id in password_params {
+            if !users.contains(&user_id) && add_problem(&mut report, "password_params", format!("user {}", user_id), "the user doesn't exist", true) {
+                self.password_params.remove(user_id).await?;
+            }
+        }
+
+        let mut stats_keys = Vec::new();
+        read_checked("tester_stats", &self.tester_stats, &mut report, |key, _| stats_keys.push(key)).await?;
+        for key in stats_keys {
+            let problem = if !users.contains(&key.user) {
+                "the user doesn't exist"
+            } else if !font_ids.contains(&key.font) {
+                "the font doesn't exist"
+            } else {
+                continue;
+            };
+            if add_problem(&mut report, "tester_stats", format!("user {} in font {}", key.user, key.font), problem, true) {
+                self.tester_stats.remove(key).await?;
+            }
+        }
+
+        Ok(report)
+    }
+
     async fn get_glyph_char(&self, glyph_id: Id<Glyph>) -> Result<char, E> {
         Ok(self.glyphs.get(glyph_id).await?.char)
     }
 }
+
+/// Reads the user's active test. Tests that can't be decoded count as missing until `State::expire_tests` removes them.
+/// How many variants a version makes of its glyphs: the same number for each glyph, and at least `Version::min_candidates` in total.
+fn candidate_count(version: &font::Version, glyph_count: usize) -> usize {
+    let min_candidates = (version.min_candidates as usize).max(1);
+    min_candidates.div_ceil(glyph_count.max(1)) * glyph_count
+}
+
+fn active_test(txn: &Txn, user_id: Id<User>) -> Result<Option<ActiveTest>, TransactionError> {
+    match txn.active_tests.get_option(user_id) {
+        Err(TransactionError::Abort(E::Decode(_))) => Ok(None),
+        result => result,
+    }
+}
+
+/// Reads every item of the tree with `f`, and reports items that can't be decoded. They're removed if `report.repair` is true.
+async fn read_checked<T, Key>(
+    name: &str,
+    tree: &Tree<T, Key>,
+    report: &mut IntegrityReport,
+    mut f: impl FnMut(Key, T),
+) -> Result<(), E>
+where
+    T: DekuRW,
+    Key: DekuRW,
+{
+    let mut stream = tree.iter_checked();
+    while let Some(result) = stream.next().await {
+        report.items += 1;
+        match result? {
+            Ok((key, item)) => f(key, item),
+            Err(undecodable) => {
+                let key: String = undecodable.key.iter().map(|byte| format!("{:02x}", byte)).collect();
+                if add_problem(report, name, format!("key {}", key), &undecodable.error.to_string(), true) {
+                    tree.remove_undecodable(&undecodable.key).await?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether the problem should be repaired now.
+fn add_problem(report: &mut IntegrityReport, tree: &str, item: String, message: &str, repairable: bool) -> bool {
+    let repaired = report.repair && repairable;
+    report.problems.push(Problem {
+        tree: tree.to_owned(),
+        item,
+        message: message.to_owned(),
+        repaired,
+    });
+    repaired
+}
+
+/// Milliseconds since the Unix epoch
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Picks the glyphs shown with the tested glyph in `TestKind::MultipleChoice` and `TestKind::Word` tests, from the font version's glyphs for other characters.
+fn choose_options(
+    kind: TestKind,
+    version_glyphs: &[(char, Id<Glyph>)],
+    glyph_id: Id<Glyph>,
+    char: char,
+) -> Vec<Id<Glyph>> {
+    let mut others: Vec<Id<Glyph>> = version_glyphs
+        .iter()
+        .filter(|&&(other_char, _)| other_char != char)
+        .map(|&(_, id)| id)
+        .collect();
+
+    let mut options = match kind {
+        TestKind::MultipleChoice => {
+            fastrand::shuffle(&mut others);
+            others.truncate(CHOICE_COUNT - 1);
+            others
+        },
+        // Made-up words can repeat characters, but never repeat the tested character
+        TestKind::Word if !others.is_empty() => {
+            let len = fastrand::usize(WORD_LENGTH);
+            (1..len).map(|_| others[fastrand::usize(..others.len())]).collect()
+        },
+        TestKind::Word => Vec::new(),
+        TestKind::Single | TestKind::Blurred | TestKind::Small => return Vec::new(),
+    };
+    options.insert(fastrand::usize(..=options.len()), glyph_id);
+
+    options
+}
@@ -0,0 +1,53 @@
+use crate::database::{Id};
+use crate::font::{Font};
+use crate::user::{User};
+use deku::prelude::*;
+use shared::stats::{TesterStats};
+
+/// Identifies a user's tests in one font. The user comes first, so each user's tests can be scanned.
+#[derive(DekuRead, DekuWrite, Clone, Copy)]
+#[deku(endian = "big")]
+pub struct Key {
+    pub user: Id<User>,
+    pub font: Id<Font>,
+}
+
+/// Every test that a user finished in one font, including control tests and tests of versions that the font had moved on from. Tests that expired aren't counted.
+#[derive(DekuRead, DekuWrite, Clone, Copy, Default)]
+#[deku(endian = "big")]
+pub struct Tests {
+    pub count: u32,
+    /// Tests where the correct character was typed or chosen
+    pub correct: u32,
+    /// The sum of the correct tests' times, in milliseconds
+    pub total_time: f64,
+    /// Versions that ended with a glyph whose score came from the user's fastest trial, counted when the version ends. Versions abandoned by a rollback still count.
+    pub winning_scores: u32,
+}
+
+impl Tests {
+    /// `time` is `None` if the test was failed
+    pub fn add(&mut self, time: Option<f64>) {
+        self.count += 1;
+        if let Some(time) = time {
+            self.correct += 1;
+            self.total_time += time;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Tests) {
+        self.count += other.count;
+        self.correct += other.correct;
+        self.total_time += other.total_time;
+        self.winning_scores += other.winning_scores;
+    }
+
+    pub fn summary(&self) -> TesterStats {
+        TesterStats {
+            tests: self.count,
+            accuracy: (self.count > 0).then(|| f64::from(self.correct) / f64::from(self.count)),
+            average_time: (self.correct > 0).then(|| self.total_time / f64::from(self.correct)),
+            winning_scores: self.winning_scores,
+        }
+    }
+}
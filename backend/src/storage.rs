@@ -0,0 +1,456 @@
+// The byte-level stores behind `database::Database`. Everything here is synchronous, and `Database` adds the types.
+
+use crate::database::{TransactionError};
+use crate::error::{InitError, Error as E};
+use sled::{Transactional as _};
+use sled::transaction::{ConflictableTransactionError, UnabortableTransactionError};
+use std::any::{Any};
+use std::cell::{RefCell};
+use std::collections::{BTreeMap};
+use std::path::{Path};
+use std::str::{FromStr};
+use std::rc::{Rc};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Key-value pairs in key order
+pub type Entries = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), E>> + Send>;
+
+/// The closure of `Storage::transaction`, which gets a view of each tree
+pub type TransactionFn<'a> = dyn Fn(&[Rc<dyn RawView>]) -> Result<(), TransactionError> + 'a;
+
+/// Every item of every tree at one point in time
+pub struct Snapshot {
+    /// Greater than every ID generated before the snapshot
+    pub next_id: u64,
+    /// Names and items of the trees, ordered by name
+    pub trees: Vec<(Vec<u8>, Vec<Item>)>,
+}
+
+/// A key and value
+pub type Item = (Vec<u8>, Vec<u8>);
+
+/// Keeps named trees of bytes, and runs transactions on them.
+pub trait Storage: Send + Sync {
+    /// Opening the same name again returns the same tree.
+    fn open_tree(&self, name: &[u8]) -> Result<Arc<dyn RawTree>, E>;
+
+    /// Returns a number that has never been returned before, for any tree.
+    fn generate_id(&self) -> Result<u64, E>;
+
+    /// Makes `generate_id` return at least `next_id` from now on, so IDs copied from another `Storage` aren't reused.
+    fn reserve_ids(&self, next_id: u64) -> Result<(), E>;
+
+    /// Copies every tree that has been opened or saved. Writes wait until copying is done, so the trees are consistent with each other.
+    fn snapshot(&self) -> Result<Snapshot, E>;
+
+    /// Returns the name and number of items of every tree that has been opened or saved, ordered by name.
+    fn tree_sizes(&self) -> Result<Vec<(Vec<u8>, usize)>, E>;
+
+    /// Runs `f` with a view of each tree, and saves its changes if it returns `Ok`. `f` runs again if it returns `TransactionError::Conflict`. The trees must come from this `Storage`.
+    fn transaction(
+        &self,
+        trees: &[&dyn RawTree],
+        f: &TransactionFn,
+    ) -> Result<(), E>;
+}
+
+pub trait RawTree: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, E>;
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), E>;
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, E>;
+
+    /// An empty prefix returns every item.
+    fn scan_prefix(&self, prefix: &[u8]) -> Entries;
+
+    /// Lets `Storage::transaction` get its own type of tree back.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A `RawTree` inside a transaction
+pub trait RawView {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TransactionError>;
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), TransactionError>;
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TransactionError>;
+
+    fn generate_id(&self) -> Result<u64, TransactionError>;
+}
+
+/// Stores trees in a directory with sled.
+pub struct Sled {
+    db: sled::Db,
+    /// Writes hold a read lock, and `snapshot` holds the write lock, because sled can't read several trees at one point in time.
+    gate: Arc<RwLock<()>>,
+}
+
+struct SledTree {
+    tree: sled::Tree,
+    gate: Arc<RwLock<()>>,
+}
+
+struct SledView(sled::transaction::TransactionalTree);
+
+/// How sled trades disk space for speed
+#[derive(Clone, Copy)]
+pub enum Mode {
+    LowSpace,
+    HighThroughput,
+}
+
+impl Sled {
+    pub fn open(path: &Path, mode: Mode) -> Result<Self, InitError> {
+        Ok(Sled {
+            db: sled::Config::default()
+                .path(path)
+                .mode(match mode {
+                    Mode::LowSpace => sled::Mode::LowSpace,
+                    Mode::HighThroughput => sled::Mode::HighThroughput,
+                })
+                .open()?,
+            gate: Arc::default(),
+        })
+    }
+
+    /// Every tree except `SLED_DEFAULT_TREE`, ordered by name
+    fn tree_names(&self) -> Vec<Vec<u8>> {
+        let mut names: Vec<Vec<u8>> = self.db
+            .tree_names()
+            .into_iter()
+            .map(|name| name.to_vec())
+            .filter(|name| name != SLED_DEFAULT_TREE)
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+/// Sled makes this tree itself, and nothing else uses it
+const SLED_DEFAULT_TREE: &[u8] = b"__sled__default";
+
+impl Storage for Sled {
+    fn open_tree(&self, name: &[u8]) -> Result<Arc<dyn RawTree>, E> {
+        Ok(Arc::new(SledTree {
+            tree: self.db.open_tree(name)?,
+            gate: self.gate.clone(),
+        }))
+    }
+
+    fn generate_id(&self) -> Result<u64, E> {
+        Ok(self.db.generate_id()?)
+    }
+
+    /// Sled can only count up, so this generates IDs until `next_id` is reached.
+    fn reserve_ids(&self, next_id: u64) -> Result<(), E> {
+        while self.db.generate_id()? < next_id {}
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Snapshot, E> {
+        let _gate = self.gate.write().unwrap_or_else(PoisonError::into_inner);
+        let names = self.tree_names();
+        let mut trees = Vec::with_capacity(names.len());
+        for name in names {
+            let items = self.db
+                .open_tree(&name)?
+                .iter()
+                .map(|result| {
+                    let (key, value) = result?;
+                    Ok((key.to_vec(), value.to_vec()))
+                })
+                .collect::<Result<_, E>>()?;
+            trees.push((name, items));
+        }
+
+        Ok(Snapshot {
+            next_id: self.db.generate_id()?,
+            trees,
+        })
+    }
+
+    /// Counting reads every item, but doesn't wait for writes.
+    fn tree_sizes(&self) -> Result<Vec<(Vec<u8>, usize)>, E> {
+        self.tree_names()
+            .into_iter()
+            .map(|name| {
+                let len = self.db.open_tree(&name)?.len();
+                Ok((name, len))
+            })
+            .collect()
+    }
+
+    fn transaction(
+        &self,
+        trees: &[&dyn RawTree],
+        f: &TransactionFn,
+    ) -> Result<(), E> {
+        let trees: Vec<&sled::Tree> = trees
+            .iter()
+            .map(|tree| &tree.as_any().downcast_ref::<SledTree>().expect("tree from another storage").tree)
+            .collect();
+
+        let _gate = self.gate.read().unwrap_or_else(PoisonError::into_inner);
+        trees[..]
+            .transaction(|trees| {
+                let views: Vec<Rc<dyn RawView>> = trees
+                    .iter()
+                    .map(|tree| Rc::new(SledView(tree.clone())) as Rc<dyn RawView>)
+                    .collect();
+                f(&views).map_err(|error| match error {
+                    TransactionError::Abort(error) => ConflictableTransactionError::Abort(error),
+                    TransactionError::Conflict => ConflictableTransactionError::Conflict,
+                })
+            })
+            .map_err(|error| match error {
+                sled::transaction::TransactionError::Abort(error) => error,
+                sled::transaction::TransactionError::Storage(error) => error.into(),
+            })
+    }
+}
+
+impl RawTree for SledTree {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, E> {
+        Ok(self.tree.get(key)?.map(|bytes| bytes.to_vec()))
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), E> {
+        let _gate = self.gate.read().unwrap_or_else(PoisonError::into_inner);
+        self.tree.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, E> {
+        let _gate = self.gate.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(self.tree.remove(key)?.map(|bytes| bytes.to_vec()))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Entries {
+        Box::new(self.tree
+            .scan_prefix(prefix)
+            .map(|result| {
+                let (key, value) = result?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl RawView for SledView {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TransactionError> {
+        Ok(self.0.get(key)?.map(|bytes| bytes.to_vec()))
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), TransactionError> {
+        self.0.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TransactionError> {
+        Ok(self.0.remove(key)?.map(|bytes| bytes.to_vec()))
+    }
+
+    fn generate_id(&self) -> Result<u64, TransactionError> {
+        Ok(self.0.generate_id()?)
+    }
+}
+
+impl From<sled::Error> for TransactionError {
+    fn from(error: sled::Error) -> Self {
+        TransactionError::Abort(error.into())
+    }
+}
+
+impl From<UnabortableTransactionError> for TransactionError {
+    fn from(error: UnabortableTransactionError) -> Self {
+        match error {
+            UnabortableTransactionError::Conflict => TransactionError::Conflict,
+            UnabortableTransactionError::Storage(error) => TransactionError::Abort(error.into()),
+        }
+    }
+}
+
+/// Keeps trees in `BTreeMap`s that are lost when it's dropped, for tests. Transactions and writes run one at a time, so transactions never conflict.
+#[derive(Default)]
+pub struct Memory {
+    trees: Mutex<BTreeMap<Vec<u8>, MemoryTree>>,
+    next_id: Arc<AtomicU64>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+#[derive(Clone)]
+struct MemoryTree {
+    items: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    write_lock: Arc<Mutex<()>>,
+}
+
+/// Changes are kept in `writes` until the transaction succeeds. `None` means the item was removed.
+struct MemoryView {
+    items: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    writes: RefCell<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Storage for Memory {
+    fn open_tree(&self, name: &[u8]) -> Result<Arc<dyn RawTree>, E> {
+        let mut trees = self.trees.lock().unwrap_or_else(PoisonError::into_inner);
+        let tree = trees.entry(name.to_vec()).or_insert_with(|| MemoryTree {
+            items: Arc::default(),
+            write_lock: self.write_lock.clone(),
+        });
+        Ok(Arc::new(tree.clone()))
+    }
+
+    fn generate_id(&self) -> Result<u64, E> {
+        Ok(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn reserve_ids(&self, next_id: u64) -> Result<(), E> {
+        self.next_id.fetch_max(next_id, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Snapshot, E> {
+        let _write_guard = self.write_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let trees = self.trees
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, tree)| {
+                let items = tree.items.read().unwrap_or_else(PoisonError::into_inner);
+                (name.clone(), items.iter().map(|(key, value)| (key.clone(), value.clone())).collect())
+            })
+            .collect();
+
+        Ok(Snapshot {
+            next_id: self.next_id.load(Ordering::Relaxed),
+            trees,
+        })
+    }
+
+    fn tree_sizes(&self) -> Result<Vec<(Vec<u8>, usize)>, E> {
+        Ok(self.trees
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(name, tree)| (name.clone(), tree.items.read().unwrap_or_else(PoisonError::into_inner).len()))
+            .collect())
+    }
+
+    fn transaction(
+        &self,
+        trees: &[&dyn RawTree],
+        f: &TransactionFn,
+    ) -> Result<(), E> {
+        let _write_guard = self.write_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let views: Vec<Rc<MemoryView>> = trees
+            .iter()
+            .map(|tree| Rc::new(MemoryView {
+                items: tree.as_any().downcast_ref::<MemoryTree>().expect("tree from another storage").items.clone(),
+                writes: RefCell::default(),
+                next_id: self.next_id.clone(),
+            }))
+            .collect();
+        let raw_views: Vec<Rc<dyn RawView>> = views.iter().map(|view| view.clone() as Rc<dyn RawView>).collect();
+
+        loop {
+            match f(&raw_views) {
+                Ok(()) => break,
+                Err(TransactionError::Abort(error)) => return Err(error),
+                // Only happens if `f` returns it itself
+                Err(TransactionError::Conflict) => {
+                    for view in &views {
+                        view.writes.borrow_mut().clear();
+                    }
+                },
+            }
+        }
+
+        for view in views {
+            let mut items = view.items.write().unwrap_or_else(PoisonError::into_inner);
+            for (key, value) in view.writes.take() {
+                match value {
+                    Some(value) => items.insert(key, value),
+                    None => items.remove(&key),
+                };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RawTree for MemoryTree {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, E> {
+        Ok(self.items.read().unwrap_or_else(PoisonError::into_inner).get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), E> {
+        let _write_guard = self.write_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.items.write().unwrap_or_else(PoisonError::into_inner).insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, E> {
+        let _write_guard = self.write_lock.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(self.items.write().unwrap_or_else(PoisonError::into_inner).remove(key))
+    }
+
+    /// The items are copied, so the tree can change while they're read.
+    fn scan_prefix(&self, prefix: &[u8]) -> Entries {
+        let items: Vec<_> = self.items
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| Ok((key.clone(), value.clone())))
+            .collect();
+        Box::new(items.into_iter())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl RawView for MemoryView {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TransactionError> {
+        Ok(match self.writes.borrow().get(key) {
+            Some(value) => value.clone(),
+            None => self.items.read().unwrap_or_else(PoisonError::into_inner).get(key).cloned(),
+        })
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), TransactionError> {
+        self.writes.borrow_mut().insert(key.to_vec(), Some(value.to_vec()));
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, TransactionError> {
+        let old = self.get(key)?;
+        self.writes.borrow_mut().insert(key.to_vec(), None);
+        Ok(old)
+    }
+
+    fn generate_id(&self) -> Result<u64, TransactionError> {
+        Ok(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "low-space" => Ok(Mode::LowSpace),
+            "high-throughput" => Ok(Mode::HighThroughput),
+            _ => Err(format!("unknown storage mode \"{}\"", string)),
+        }
+    }
+}
@@ -1,22 +1,110 @@
+use crate::error::{Error as E};
 use deku::prelude::*;
+use shared::util::{read_rest};
+use std::borrow::{Cow};
 
 #[derive(DekuRead, DekuWrite)]
 #[deku(endian = "big")]
 pub struct User {
-    #[deku(bytes_read = "HASH_CONFIG.hash_length")]
+    salt: [u8; SALT_LENGTH],
+    #[deku(bytes_read = "HASH_LENGTH")]
     password_hash: Vec<u8>,
     #[deku(bits_read = "deku::rest.len()")]
     name: Vec<u8>,
 }
 
-const HASH_CONFIG: argon2::Config = argon2::Config {
-    ad: &[],
-    hash_length: 16,
-    lanes: 1,
-    mem_cost: 4096,
-    secret: &[],
-    thread_mode: argon2::ThreadMode::Sequential,
-    time_cost: 3,
-    variant: argon2::Variant::Argon2id,
-    version: argon2::Version::Version13,
-};
+/// Finds users in the index of names
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct Name {
+    #[deku(reader = "read_rest(deku::rest, deku::ctx::Endian::Big)")]
+    bytes: Vec<u8>,
+}
+
+/// The argon2 costs of a password hash. Each user's costs are stored, so changing them only affects new hashes.
+#[derive(DekuRead, DekuWrite, Clone, Copy, PartialEq, Eq)]
+#[deku(endian = "big")]
+pub struct HashParams {
+    /// In kibibytes
+    pub memory_cost: u32,
+    /// The number of passes over the memory
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+const SALT_LENGTH: usize = 16;
+
+const HASH_LENGTH: u32 = 16;
+
+impl Default for HashParams {
+    /// The costs of every password hashed before they could be changed
+    fn default() -> Self {
+        HashParams {
+            memory_cost: 4096,
+            time_cost: 3,
+            lanes: 1,
+        }
+    }
+}
+
+impl HashParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.time_cost == 0 || self.lanes == 0 {
+            Err("password time cost and lanes must be positive".to_owned())
+        } else if self.memory_cost < 8 * self.lanes {
+            Err("password memory cost must be at least 8 KiB for each lane".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            ad: &[],
+            hash_length: HASH_LENGTH,
+            lanes: self.lanes,
+            mem_cost: self.memory_cost,
+            secret: &[],
+            thread_mode: argon2::ThreadMode::Sequential,
+            time_cost: self.time_cost,
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+        }
+    }
+}
+
+impl User {
+    pub fn new(name: &str, password: &str, params: &HashParams) -> Result<Self, E> {
+        let mut salt = [0; SALT_LENGTH];
+        getrandom::getrandom(&mut salt)?;
+
+        Ok(User {
+            password_hash: argon2::hash_raw(password.as_bytes(), &salt, &params.config())?,
+            salt,
+            name: name.as_bytes().to_vec(),
+        })
+    }
+
+    pub fn name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    pub fn name_key(&self) -> Name {
+        Name {
+            bytes: self.name.clone(),
+        }
+    }
+
+    /// `params` must be the ones that the password was hashed with.
+    pub fn verify_password(&self, password: &str, params: &HashParams) -> Result<bool, E> {
+        Ok(argon2::verify_raw(password.as_bytes(), &self.salt, &self.password_hash, &params.config())?)
+    }
+}
+
+impl Name {
+    pub fn new(name: &str) -> Self {
+        Name {
+            bytes: name.as_bytes().to_vec(),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use async_std::channel::{self, Receiver, Sender};
+use crate::active_test::{Expiry};
+use crate::state::{State};
+use shared::glyph::{Mutation};
+use std::sync::{Arc};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The shortest interval between passes, so the worker can't be made to spin
+pub const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Settings for the background task that keeps the fonts' queues filled and moves fonts on to their next version, so that `State::add_next_test` rarely has to.
+///
+/// A version's candidates are made a few at a time, keeping `queue_depth` of them queued until the version's `min_candidates` run out. The next version's candidates are mutated from the current version's winners, so they can't be made before every candidate of the current version has been tested enough. Submissions that shorten a queue wake the worker up, and if a queue is empty before its next pass, `State::add_next_test` does the work itself.
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Each font version gets at least this many new candidates, so there are enough tests for many users at once. Every glyph gets the same number of variants, so there can be more.
+    pub min_candidates: usize,
+    /// The worker makes more of a version's candidates whenever fewer than this many are queued
+    pub queue_depth: usize,
+    /// Used for the candidates of new versions
+    pub mutation: Mutation,
+    /// Time between passes over every font, at least `MIN_INTERVAL`. Raising it throttles the worker.
+    pub interval: Duration,
+    /// Time between garbage collections, or `None` to only collect when requested. Collections happen after a pass, so they're at most one per pass.
+    pub gc_interval: Option<Duration>,
+    /// Active tests are removed this long after they start, if the user hasn't finished them
+    pub test_lifetime: Duration,
+    pub expiry: Expiry,
+}
+
+/// Shared with the worker to pause, throttle or wake it up
+#[derive(Clone)]
+pub struct Handle {
+    pub min_candidates: usize,
+    pub queue_depth: usize,
+    pub mutation: Mutation,
+    pub gc_interval: Option<Duration>,
+    pub test_lifetime: Duration,
+    pub expiry: Expiry,
+    paused: Arc<AtomicBool>,
+    interval_millis: Arc<AtomicU64>,
+    wake: Sender<()>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            min_candidates: 16,
+            queue_depth: 8,
+            mutation: Mutation::default(),
+            interval: Duration::from_secs(5),
+            gc_interval: Some(Duration::from_secs(60 * 60)),
+            test_lifetime: Duration::from_secs(10 * 60),
+            expiry: Expiry::Requeue,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_candidates == 0 {
+            Err("minimum candidates must be positive".to_owned())
+        } else if self.queue_depth == 0 {
+            Err("queue depth must be positive".to_owned())
+        } else if self.mutation.position < 0 || self.mutation.curviness < 0 {
+            Err("mutation position and curviness can't be negative".to_owned())
+        } else if !(self.mutation.radians.is_finite() && self.mutation.radians >= 0.0) {
+            Err("mutation radians must be a number that isn't negative".to_owned())
+        } else if self.interval < MIN_INTERVAL {
+            Err(format!("worker interval must be at least {} milliseconds", MIN_INTERVAL.as_millis()))
+        } else if self.gc_interval.is_some_and(|interval| interval.is_zero()) {
+            Err("garbage collection interval must be positive, or \"never\"".to_owned())
+        } else if self.test_lifetime.is_zero() {
+            Err("test lifetime must be positive".to_owned())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The receiver must be passed to `run`.
+pub fn channel(config: Config) -> (Handle, Receiver<()>) {
+    // One pending wake-up is enough, because a pass handles every font
+    let (wake, receiver) = channel::bounded(1);
+    let handle = Handle {
+        min_candidates: config.min_candidates,
+        queue_depth: config.queue_depth,
+        mutation: config.mutation,
+        gc_interval: config.gc_interval,
+        test_lifetime: config.test_lifetime,
+        expiry: config.expiry,
+        paused: Arc::new(AtomicBool::new(false)),
+        interval_millis: Arc::new(AtomicU64::new(config.interval.as_millis() as u64)),
+        wake,
+    };
+    (handle, receiver)
+}
+
+/// Runs forever, doing a pass every interval or when woken up. Each pass removes expired tests, advances fonts and fills their queues. Garbage is collected after a pass once `Config::gc_interval` has passed. Errors are logged, and don't stop the worker.
+pub async fn run(state: State, wake: Receiver<()>) {
+    let mut last_gc = Instant::now();
+    loop {
+        if !state.worker().is_paused() {
+            if let Err(error) = state.expire_tests().await {
+                eprintln!("Error while expiring tests: {}", error);
+            }
+            if let Err(error) = state.advance_fonts().await {
+                eprintln!("Error in evolution worker: {}", error);
+            }
+            if let Err(error) = state.fill_queues().await {
+                eprintln!("Error while filling queues: {}", error);
+            }
+            if state.worker().gc_interval.is_some_and(|interval| last_gc.elapsed() >= interval) {
+                last_gc = Instant::now();
+                match state.collect_garbage(false).await {
+                    Ok(report) => println!("Collected garbage: {} versions, {} glyphs, {} other records", report.versions, report.glyphs, report.records),
+                    Err(error) => eprintln!("Error in garbage collection: {}", error),
+                }
+            }
+        }
+        let _ = async_std::future::timeout(state.worker().interval(), wake.recv()).await;
+    }
+}
+
+impl Handle {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        if !paused {
+            self.wake();
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_millis.load(Ordering::Relaxed))
+    }
+
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval_millis.store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Starts a pass now instead of waiting for the interval. Does nothing if a wake-up is already pending.
+    pub fn wake(&self) {
+        let _ = self.wake.try_send(());
+    }
+}
@@ -0,0 +1,63 @@
+use async_std::task;
+use backend::assets::{self, Asset};
+use tide::http::{Method, Request, Response, Url};
+
+static STYLE: Asset = Asset {
+    content_type: "text/css; charset=utf-8",
+    hash: "0123456789abcdef",
+    identity: b"body { color: black; }",
+    gzip: Some(b"gzip"),
+    brotli: Some(b"br"),
+};
+
+async fn get(server: &tide::Server<()>, path: &str, headers: &[(&str, &str)]) -> Response {
+    let mut req = Request::new(Method::Get, Url::parse("http://localhost").unwrap().join(path).unwrap());
+    for (name, value) in headers {
+        req.insert_header(*name, *value);
+    }
+    server.respond(req).await.unwrap()
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response.header(name).map(|values| values.as_str().to_owned())
+}
+
+#[test]
+fn assets_are_negotiated_and_cached() {
+    task::block_on(async {
+        let mut server = tide::new();
+        assets::serve(&mut server, "/style.css", &STYLE, false);
+        assets::serve(&mut server, "/assets/0123456789abcdef/style.css", &STYLE, true);
+
+        let mut response = get(&server, "/style.css", &[]).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(header(&response, "Content-Type").as_deref(), Some("text/css; charset=utf-8"));
+        assert_eq!(header(&response, "Content-Encoding"), None);
+        assert_eq!(header(&response, "ETag").as_deref(), Some("\"0123456789abcdef\""));
+        assert_eq!(header(&response, "Cache-Control").as_deref(), Some("no-cache"));
+        assert_eq!(response.body_bytes().await.unwrap(), STYLE.identity);
+
+        let mut response = get(&server, "/style.css", &[("Accept-Encoding", "gzip, deflate, br")]).await;
+        assert_eq!(header(&response, "Content-Encoding").as_deref(), Some("br"));
+        assert_eq!(header(&response, "ETag").as_deref(), Some("\"0123456789abcdef-br\""));
+        assert_eq!(response.body_bytes().await.unwrap(), b"br");
+
+        let response = get(&server, "/style.css", &[("Accept-Encoding", "br;q=0, *")]).await;
+        assert_eq!(header(&response, "Content-Encoding").as_deref(), Some("gzip"));
+
+        let response = get(&server, "/assets/0123456789abcdef/style.css", &[]).await;
+        assert_eq!(header(&response, "Cache-Control").as_deref(), Some("public, max-age=31536000, immutable"));
+
+        // A cached variant is still current when the client now accepts another encoding
+        let mut response = get(&server, "/style.css", &[
+            ("Accept-Encoding", "gzip"),
+            ("If-None-Match", "\"0123456789abcdef-br\""),
+        ]).await;
+        assert_eq!(response.status(), 304);
+        assert_eq!(header(&response, "ETag").as_deref(), Some("\"0123456789abcdef-gzip\""));
+        assert!(response.body_bytes().await.unwrap().is_empty());
+
+        let response = get(&server, "/style.css", &[("If-None-Match", "\"fedcba9876543210\"")]).await;
+        assert_eq!(response.status(), 200);
+    });
+}
@@ -0,0 +1,80 @@
+use backend::config::{Config};
+use std::net::{IpAddr};
+use std::time::{Duration};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|&arg| arg.to_owned()).collect()
+}
+
+/// A config file in the temporary directory, named after the test so tests can run at the same time
+fn config_file(name: &str, text: &str) -> String {
+    let path = std::env::temp_dir().join(format!("fontgen-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn flags_override_the_config_file() {
+    let path = config_file("override", r#"
+        [server]
+        data_directory = "data"
+        storage_mode = "high-throughput"
+        operators = "ann, bob"
+        metrics_clients = "10.0.0.1"
+
+        [evolution]
+        min_candidates = 4
+        test_lifetime = 1.5
+        gc_interval = "never"
+
+        [rate_limit]
+        persist = true
+    "#);
+    let args = args(&["--config", &path, "--min-candidates=8", "gc", "--dry-run"]);
+    let (config, rest) = Config::load(&args).unwrap();
+
+    assert_eq!(config.data_directory.to_str(), Some("data"));
+    assert_eq!(config.operators, ["ann", "bob"]);
+    assert_eq!(config.metrics_clients, [IpAddr::from([10, 0, 0, 1])]);
+    assert_eq!(config.worker.min_candidates, 8);
+    assert_eq!(config.worker.test_lifetime, Duration::from_millis(1500));
+    assert_eq!(config.worker.gc_interval, None);
+    assert!(config.rate_limit.persist);
+    assert_eq!(rest, ["gc", "--dry-run"]);
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    for text in [
+        "[server]\nport = 80",
+        "[server]\nmetrics_clients = \"localhost\"",
+        "[evolution]\nmin_candidates = 0",
+        "[evolution]\nqueue_depth = 0",
+        "[evolution]\nworker_interval = 0",
+        "[evolution]\nmutation_position = -1",
+        "[evolution]\nmutation_curviness = -32768",
+        "[evolution]\nmutation_radians = -0.5",
+        "[evolution]\nmutation_radians = nan",
+        "[evolution]\ntest_lifetime = -1",
+        "[evolution]\ntest_lifetime = nan",
+        "[evolution]\ntest_lifetime = 1e300",
+        "[evolution]\ngc_interval = 1e20",
+        "[evolution]\ngc_interval = 0",
+        "[evolution]\nselection_rule = \"fastest\"",
+        // Penalties in milliseconds would outweigh normalized times
+        "[evolution]\nnormalization = \"ratio\"\nreference_font = 1",
+        "[evolution]\nnormalization = \"z-score\"\nreference_font = 1\nerror_penalty = 1\nconfusion_penalty = 1\nselection_rule = \"posterior:500:2\"",
+        "[evaluator]\nmax_generations = 0",
+        "[passwords]\nmemory_cost = 4\nlanes = 1",
+        "[rate_limit]\nper_second = 0",
+        "[rate_limit]\npersist = \"sometimes\"",
+        "min_candidates = 4",
+    ] {
+        let path = config_file("invalid", text);
+        assert!(Config::load(&args(&["--config", &path])).is_err(), "{}", text);
+    }
+    let path = config_file("normalized", "[evolution]\nnormalization = \"ratio\"\nreference_font = 1\nerror_penalty = 2\nconfusion_penalty = 2");
+    assert!(Config::load(&args(&["--config", &path])).is_ok());
+    assert!(Config::load(&args(&["--unknown", "1"])).is_err());
+    assert!(Config::load(&args(&["--min-candidates"])).is_err());
+}
@@ -0,0 +1,176 @@
+#![allow(clippy::manual_div_ceil)]
+
+use async_std::stream::{StreamExt};
+use async_std::task;
+use backend::database::{Database, Id, Index, Tree};
+use backend::error::{Error};
+use deku::prelude::*;
+use shared::glyph::{Glyph};
+
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+struct CharKey {
+    char: u32,
+}
+
+fn glyph(char: char) -> Glyph {
+    Glyph::new(char)
+}
+
+fn glyphs_by_char(glyph: &Glyph) -> Vec<CharKey> {
+    vec![CharKey {
+        char: glyph.char.into(),
+    }]
+}
+
+async fn find(index: &Index<Glyph, Id<Glyph>, CharKey>, char: char) -> Vec<Id<Glyph>> {
+    let mut ids = Vec::new();
+    let mut stream = index.get(CharKey { char: char.into() }).unwrap();
+    while let Some(result) = stream.next().await {
+        let (id, glyph) = result.unwrap();
+        assert_eq!(glyph.char, char);
+        ids.push(id);
+    }
+    ids
+}
+
+#[test]
+fn failed_transactions_change_nothing() {
+    task::block_on(async {
+        let db = Database::in_memory();
+        let glyphs = db.tree::<Glyph, Id<Glyph>>(b"glyphs").await.unwrap();
+
+        let result = db.transaction((&glyphs,), |(glyphs,)| {
+            glyphs.insert(&glyph('a'))?;
+            Err::<(), _>(Error::not_logged_in().into())
+        }).await;
+        assert!(result.is_err());
+        assert!(glyphs.keys().next().await.is_none());
+
+        let id = db.transaction((&glyphs,), |(glyphs,)| glyphs.insert(&glyph('b'))).await.unwrap();
+        assert_eq!(glyphs.get(id).await.unwrap().char, 'b');
+    });
+}
+
+#[test]
+fn indexes_follow_changes() {
+    task::block_on(async {
+        let db = Database::in_memory();
+        let mut glyphs: Tree<Glyph> = db.tree(b"glyphs").await.unwrap();
+        let a = glyphs.insert(&glyph('a')).await.unwrap();
+        // Existing items are indexed when the index is added
+        let by_char = glyphs.add_index(b"glyphs_by_char", glyphs_by_char).await.unwrap();
+        assert_eq!(find(&by_char, 'a').await, [a]);
+
+        let b = glyphs.insert(&glyph('b')).await.unwrap();
+        let a2 = glyphs.insert(&glyph('a')).await.unwrap();
+        assert_eq!(find(&by_char, 'a').await, [a, a2]);
+        assert_eq!(find(&by_char, 'b').await, [b]);
+
+        glyphs.insert_with_key(a, &glyph('c')).await.unwrap();
+        assert_eq!(find(&by_char, 'a').await, [a2]);
+        assert_eq!(find(&by_char, 'c').await, [a]);
+
+        glyphs.remove(b).await.unwrap();
+        assert!(find(&by_char, 'b').await.is_empty());
+
+        let d = db.transaction((&glyphs,), |(glyphs,)| {
+            glyphs.remove(a2)?;
+            glyphs.insert(&glyph('d'))
+        }).await.unwrap();
+        assert!(find(&by_char, 'a').await.is_empty());
+        assert_eq!(find(&by_char, 'd').await, [d]);
+
+        // Failed transactions don't change indexes either
+        let result = db.transaction((&glyphs,), |(glyphs,)| {
+            glyphs.remove(d)?;
+            Err::<(), _>(Error::not_logged_in().into())
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(find(&by_char, 'd').await, [d]);
+    });
+}
+
+#[test]
+fn unique_indexes_find_items_in_transactions() {
+    task::block_on(async {
+        let db = Database::in_memory();
+        let mut glyphs: Tree<Glyph> = db.tree(b"glyphs").await.unwrap();
+        let a = glyphs.insert(&glyph('a')).await.unwrap();
+        glyphs.add_index(b"glyphs_by_char", glyphs_by_char).await.unwrap();
+
+        // The index is rebuilt when it becomes unique
+        let mut glyphs: Tree<Glyph> = db.tree(b"glyphs").await.unwrap();
+        let by_char = glyphs.add_unique_index(b"glyphs_by_char", glyphs_by_char).await.unwrap();
+        assert_eq!(find(&by_char, 'a').await, [a]);
+        assert_eq!(glyphs.check_indexes(false).await.unwrap(), 0);
+
+        let (found, b) = db.transaction((&glyphs,), |(glyphs,)| {
+            let found = glyphs.get_unique(&by_char, CharKey { char: 'a'.into() })?.map(|(id, _)| id);
+            let b = glyphs.insert(&glyph('b'))?;
+            // Items inserted in the transaction are found too
+            assert!(glyphs.get_unique(&by_char, CharKey { char: 'b'.into() })?.is_some());
+            Ok((found, b))
+        }).await.unwrap();
+        assert_eq!(found, Some(a));
+        assert_eq!(find(&by_char, 'b').await, [b]);
+
+        // Another item with the same index key is refused
+        assert!(glyphs.insert(&glyph('a')).await.is_err());
+        assert_eq!(find(&by_char, 'a').await, [a]);
+        glyphs.insert_with_key(a, &glyph('c')).await.unwrap();
+        glyphs.insert(&glyph('a')).await.unwrap();
+    });
+}
+
+#[test]
+fn backups_restore_every_tree() {
+    task::block_on(async {
+        let db = Database::in_memory();
+        let mut glyphs: Tree<Glyph> = db.tree(b"glyphs").await.unwrap();
+        glyphs.add_index(b"glyphs_by_char", glyphs_by_char).await.unwrap();
+        let a = glyphs.insert(&glyph('a')).await.unwrap();
+        let archive = db.backup().await.unwrap();
+
+        let restored = Database::in_memory();
+        let summary = restored.restore(&archive).await.unwrap();
+        assert_eq!((summary.trees, summary.items), (2, 2));
+        let mut restored_glyphs: Tree<Glyph> = restored.tree(b"glyphs").await.unwrap();
+        let by_char = restored_glyphs.add_index(b"glyphs_by_char", glyphs_by_char).await.unwrap();
+        assert_eq!(restored_glyphs.get(a).await.unwrap().char, 'a');
+        assert_eq!(find(&by_char, 'a').await, [a]);
+
+        // IDs from the backup aren't generated again
+        let b = restored_glyphs.insert(&glyph('b')).await.unwrap();
+        assert!(b > a);
+
+        // Restoring over existing items is refused
+        assert!(restored.restore(&archive).await.is_err());
+    });
+}
+
+#[test]
+fn damaged_backups_are_rejected() {
+    task::block_on(async {
+        let db = Database::in_memory();
+        let glyphs: Tree<Glyph> = db.tree(b"glyphs").await.unwrap();
+        glyphs.insert(&glyph('a')).await.unwrap();
+        let archive = db.backup().await.unwrap();
+
+        for damaged in [
+            archive[..archive.len() - 1].to_vec(),
+            archive[..10].to_vec(),
+            {
+                let mut archive = archive.clone();
+                let last_value = archive.len() - 10;
+                archive[last_value] ^= 1;
+                archive
+            },
+        ] {
+            let restored = Database::in_memory();
+            assert!(restored.restore(&damaged).await.is_err());
+            let restored_glyphs: Tree<Glyph> = restored.tree(b"glyphs").await.unwrap();
+            assert!(restored_glyphs.keys().next().await.is_none());
+        }
+    });
+}
@@ -0,0 +1,47 @@
+use async_std::task;
+use backend::database::{Database, Id};
+use backend::error::{Error};
+use backend::state::{State};
+use backend::user::{HashParams};
+use backend::{evaluator, fitness, worker};
+use shared::error::{ErrorCode};
+use tide::{Body, StatusCode};
+
+async fn new_state() -> State {
+    let (worker, _) = worker::channel(worker::Config::default());
+    State::new(Database::in_memory(), fitness::Config::default(), evaluator::Config::default(), worker, HashParams::default())
+        .await
+        .unwrap()
+}
+
+fn assert_error<T>(result: Result<T, Error>, code: ErrorCode, status: StatusCode) {
+    let error = result.err().expect("expected an error");
+    assert_eq!((error.code(), error.status()), (code, status), "{}", error);
+}
+
+#[test]
+fn errors_have_codes_and_statuses() {
+    task::block_on(async {
+        let state = new_state().await;
+        state.add_user("ann", "password").await.unwrap();
+
+        assert_error(state.add_user("ann", "other").await, ErrorCode::NameTaken, StatusCode::Conflict);
+        assert_error(state.log_in("ann", "wrong").await, ErrorCode::InvalidLogin, StatusCode::Unauthorized);
+        assert_error(state.delete_user("bob").await, ErrorCode::NotFound, StatusCode::NotFound);
+        let missing: Id<_> = "12345".parse().unwrap();
+        assert_error(state.font_history(missing).await, ErrorCode::NotFound, StatusCode::NotFound);
+        assert_error(Database::in_memory().restore(b"not a backup").await, ErrorCode::InvalidBackup, StatusCode::BadRequest);
+    });
+}
+
+#[test]
+fn error_responses_use_stable_codes() {
+    task::block_on(async {
+        assert_eq!(Error::not_operator().status(), StatusCode::Forbidden);
+        let body = Body::from_json(&Error::not_logged_in().to_response()).unwrap();
+        assert_eq!(
+            body.into_string().await.unwrap(),
+            r#"{"code":"not_logged_in","message":"You must be logged in to do this."}"#,
+        );
+    });
+}
@@ -0,0 +1,730 @@
+// Runs the whole evolution flow against `Database::in_memory`. The worker isn't spawned, so tests call its `State` methods themselves or let `State::add_next_test` do its work.
+
+use async_std::task;
+use deku::{DekuContainerWrite};
+use backend::active_test::{Expiry, Keystroke, TestKind};
+use backend::database::{Database, Id, Tree};
+use backend::font::{self, Font};
+use backend::state::{State};
+use backend::user::{HashParams, User};
+use backend::{evaluator, fitness, worker};
+use shared::diff;
+use shared::event::{FontEvent};
+use shared::glyph::{Glyph, Mutation};
+use shared::history::{CandidateSummary};
+use shared::presentation::{Presentation};
+use shared::report::{GarbageReport};
+use shared::util::{DekuRW};
+use std::collections::{BTreeSet};
+use std::time::{Duration};
+
+async fn new_state(fitness: fitness::Config, worker: worker::Config) -> State {
+    let (worker, _) = worker::channel(worker);
+    State::new(Database::in_memory(), fitness, evaluator::Config::default(), worker, HashParams::default())
+        .await
+        .unwrap()
+}
+
+/// Small queues and few trials, so a version is finished after a few tests. The default selection rule needs two times for a finite fitness.
+fn quick_config() -> (fitness::Config, worker::Config) {
+    let fitness = fitness::Config {
+        min_trials: 2,
+        ..fitness::Config::default()
+    };
+    let worker = worker::Config {
+        min_candidates: 2,
+        gc_interval: None,
+        ..worker::Config::default()
+    };
+    (fitness, worker)
+}
+
+/// A triangle, so the glyph has a shape that can be rasterized
+fn glyph(char: char) -> Glyph {
+    let mut glyph = Glyph::new(char);
+    for (index, position) in [[8192, 4096], [12288, 12288], [4096, 12288]].into_iter().enumerate() {
+        glyph.add_point(0);
+        glyph.update_point(0, index, |point| point.position = position);
+    }
+    glyph
+}
+
+/// Takes tests of the font until every candidate of its current version was tested enough, then starts the next version. The font must only have glyphs for 'a'.
+async fn finish_version(state: &State, font: Id<Font>, user: Id<User>) {
+    while state.font_candidates(font).await.unwrap().iter().any(|candidate| candidate.queued) {
+        state.add_next_test(font, user).await.unwrap();
+        state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+    }
+    state.advance_fonts().await.unwrap();
+}
+
+/// Parses the ID the same way as IDs in URLs
+fn id<T>(id: u64) -> Id<T> {
+    id.to_string().parse().unwrap()
+}
+
+fn shown_glyph(presentation: Option<Presentation>) -> String {
+    match presentation {
+        Some(Presentation::Single { glyph }) => glyph,
+        _ => panic!("expected a single glyph"),
+    }
+}
+
+#[test]
+fn typing_tests_advance_the_font() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a'), glyph('b')], TestKind::Single).await.unwrap();
+
+        let mut tests = 0;
+        while state.font_history(font).await.unwrap().len() < 3 {
+            assert!(tests < 100, "the font didn't advance");
+            tests += 1;
+
+            // Without a worker, the test that finds the queue empty starts the next version
+            state.add_next_test(font, user).await.unwrap();
+            // Typing the wrong character first only adds a confusion
+            for char in ['a', 'b'] {
+                if state.get_test(user).await.unwrap().is_some() {
+                    state.submit_keystroke(user, Keystroke { char, time: 500.0 }).await.unwrap();
+                }
+            }
+        }
+
+        let history = state.font_history(font).await.unwrap();
+        assert_eq!(history[0].parent, Some(history[1].id));
+        assert_eq!(history[1].parent, Some(history[2].id));
+        assert_eq!(history[2].parent, None);
+        for version in &history {
+            let chars: Vec<char> = version.glyphs.iter().map(|glyph| glyph.char).collect();
+            assert_eq!(chars, ['a', 'b']);
+        }
+
+        let matrix = state.confusion_matrix(id(history[1].id)).await.unwrap();
+        assert_eq!(matrix.chars, ['a', 'b']);
+    });
+}
+
+#[test]
+fn repeated_mistakes_are_one_confusion() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        let version = state.font_history(font).await.unwrap()[0].id;
+
+        state.add_next_test(font, user).await.unwrap();
+        for char in ['b', 'b', 'b', 'c', 'a'] {
+            state.submit_keystroke(user, Keystroke { char, time: 500.0 }).await.unwrap();
+        }
+
+        let matrix = state.confusion_matrix(id(version)).await.unwrap();
+        assert_eq!(matrix.chars, ['a', 'b', 'c']);
+        assert_eq!(matrix.counts[0], [0, 1, 1]);
+    });
+}
+
+#[test]
+fn implausible_times_are_rejected() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        state.add_next_test(font, user).await.unwrap();
+
+        let lifetime = worker.test_lifetime.as_millis() as f64;
+        for time in [-500.0, 0.0, 1.0, lifetime + 1.0, f64::MAX] {
+            assert!(state.submit_keystroke(user, Keystroke { char: 'a', time }).await.is_err(), "{} was accepted", time);
+            assert!(state.submit_choice(user, 0, time).await.is_err(), "{} was accepted", time);
+        }
+        // Nothing was recorded, so the test is still running
+        assert!(state.get_test(user).await.unwrap().is_some());
+        state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+        assert!(state.get_test(user).await.unwrap().is_none());
+    });
+}
+
+#[test]
+fn the_reference_font_doesnt_evolve() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let db = Database::in_memory();
+        let (handle, _) = worker::channel(worker);
+        let setup = State::new(db.clone(), fitness, evaluator::Config::default(), handle.clone(), HashParams::default()).await.unwrap();
+        let reference_font = setup.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        let fitness = fitness::Config {
+            reference_font: Some(reference_font),
+            ..fitness
+        };
+        let state = State::new(db, fitness, evaluator::Config::default(), handle, HashParams::default()).await.unwrap();
+        let user = state.add_user("ann", "password").await.unwrap();
+        let current = state.font_history(reference_font).await.unwrap()[0].glyphs[0].glyph;
+        let current = shown_glyph(Some(Presentation::Single { glyph: state.get_glyph(id(current)).await.unwrap().to_svg_path_d() }));
+
+        for _ in 0..10 {
+            state.add_next_test(reference_font, user).await.unwrap();
+            assert_eq!(shown_glyph(state.get_test(user).await.unwrap()), current);
+            state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+        }
+        state.advance_fonts().await.unwrap();
+        assert!(state.evolve_offline(reference_font, 1).await.is_err());
+        assert_eq!(state.font_history(reference_font).await.unwrap().len(), 1);
+    });
+}
+
+#[test]
+fn offline_evolution_records_ancestry() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let font = state.add_font(vec![glyph('a'), glyph('b')], TestKind::Single).await.unwrap();
+
+        let too_many = evaluator::Config::default().max_generations + 1;
+        assert!(state.evolve_offline(font, too_many).await.is_err());
+        let current = state.evolve_offline(font, 3).await.unwrap();
+        let history = state.font_history(font).await.unwrap();
+        assert_eq!(history.len(), 4);
+        assert_eq!(history[0].id, u64::from(current));
+
+        for version_glyph in &history[0].glyphs {
+            let ancestry = state.glyph_ancestry(id(version_glyph.glyph)).await.unwrap();
+            assert!(ancestry.iter().all(|ancestor| ancestor.char == version_glyph.char));
+            // The last ancestor was added with the font
+            assert_eq!(ancestry.last().unwrap().font_version, None);
+            assert_eq!(ancestry.last().unwrap().glyph, history[3].glyphs.iter().find(|glyph| glyph.char == version_glyph.char).unwrap().glyph);
+        }
+    });
+}
+
+#[test]
+fn candidates_are_reproducible() {
+    let glyphs = [glyph('a'), glyph('b')];
+    let variants = |seed| Glyph::generate_variants(glyphs.iter(), &Mutation::default(), &fastrand::Rng::with_seed(seed));
+    assert!(variants(7) == variants(7));
+    assert!(variants(7) != variants(8));
+
+    // Versions stored before seeds were recorded only have the next version's ID
+    let version = font::Version::read(&7u64.to_be_bytes()).unwrap();
+    assert!(version.next_version == id(7));
+    assert_eq!(version.seed, 0);
+    assert_eq!(version.min_candidates, 1);
+    assert!(version.parent.is_none());
+}
+
+#[test]
+fn fonts_from_before_test_kinds_are_read() {
+    // The first and current versions, then two candidates
+    let bytes: Vec<u8> = [1u64, 2, 3, 4].iter().flat_map(|id| id.to_be_bytes()).collect();
+    let font = Font::read(&bytes).unwrap();
+    assert!(font.current_version == id(2));
+    assert!(font.candidates == [id(3), id(4)]);
+    assert!(font.test_kind == TestKind::Single);
+
+    let font = Font {
+        test_kind: TestKind::Word,
+        ..font
+    };
+    let font = Font::read(&font.to_bytes().unwrap()).unwrap();
+    assert!(font.candidates == [id(3), id(4)]);
+    assert!(font.test_kind == TestKind::Word);
+}
+
+#[test]
+fn version_diffs_take_the_shortest_turn() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        state.evolve_offline(font, 1).await.unwrap();
+        let history = state.font_history(font).await.unwrap();
+        assert!(state.version_diff(id(history[1].id), id(history[0].id)).await.is_ok());
+        assert!(state.version_diff(id(history[1].id), id(u64::MAX)).await.is_err());
+    });
+
+    let mut old = glyph('a');
+    let mut new = glyph('a');
+    old.update_point(0, 0, |point| point.radians = 3.0);
+    new.update_point(0, 0, |point| point.radians = -3.0);
+    let (points, shape_changed) = diff::compare_glyphs(&old, &new);
+    assert!(!shape_changed);
+    assert!((points[0].radians - (std::f32::consts::TAU - 6.0)).abs() < 1e-5, "{}", points[0].radians);
+}
+
+#[test]
+fn roll_back_and_fork() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        state.evolve_offline(font, 2).await.unwrap();
+        let history = state.font_history(font).await.unwrap();
+        let first = history.last().unwrap();
+
+        state.roll_back_font(font, id(first.id)).await.unwrap();
+        let rolled_back = state.font_history(font).await.unwrap();
+        assert_eq!(rolled_back.len(), 2);
+        assert_eq!(rolled_back[0].parent, Some(first.id));
+        assert_eq!(rolled_back[0].glyphs[0].glyph, first.glyphs[0].glyph);
+
+        // Abandoned versions aren't in the history anymore
+        assert!(state.roll_back_font(font, id(history[0].id)).await.is_err());
+
+        assert!(state.fork_font(id(u64::MAX), TestKind::Word).await.is_err());
+        let fork = state.fork_font(id(history[1].id), TestKind::Word).await.unwrap();
+        let forked = state.font_history(fork).await.unwrap();
+        assert_eq!(forked.len(), 1);
+        assert_eq!(forked[0].parent, Some(history[1].id));
+        assert_eq!(forked[0].glyphs[0].glyph, history[1].glyphs[0].glyph);
+    });
+}
+
+#[test]
+fn changing_the_test_kind_starts_a_version() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        state.add_next_test(font, user).await.unwrap();
+        state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+
+        state.set_test_kind(font, TestKind::Blurred).await.unwrap();
+        let history = state.font_history(font).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].parent, Some(history[1].id));
+        assert_eq!(history[0].glyphs[0].glyph, history[1].glyphs[0].glyph);
+        // Times of single glyphs aren't pooled with times of blurred ones
+        assert!(state.confusion_matrix(id(history[0].id)).await.unwrap().chars.is_empty());
+
+        state.add_next_test(font, user).await.unwrap();
+        assert!(matches!(state.get_test(user).await.unwrap(), Some(Presentation::Blurred { .. })));
+
+        state.set_test_kind(font, TestKind::Blurred).await.unwrap();
+        assert_eq!(state.font_history(font).await.unwrap().len(), 2);
+    });
+}
+
+#[test]
+fn garbage_collection_keeps_reachable_data() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let font = state.add_font(vec![glyph('a'), glyph('b')], TestKind::Single).await.unwrap();
+        state.evolve_offline(font, 3).await.unwrap();
+        let first = state.font_history(font).await.unwrap().pop().unwrap();
+        state.roll_back_font(font, id(first.id)).await.unwrap();
+        let history = state.font_history(font).await.unwrap();
+
+        let dry_run = state.collect_garbage(true).await.unwrap();
+        assert_eq!(dry_run.versions, 3);
+        assert!(dry_run.glyphs > 0);
+
+        let report = state.collect_garbage(false).await.unwrap();
+        assert_eq!((report.versions, report.glyphs, report.records), (dry_run.versions, dry_run.glyphs, dry_run.records));
+        let again = state.collect_garbage(true).await.unwrap();
+        assert_eq!((again.versions, again.glyphs, again.records), (0, 0, 0));
+
+        assert_eq!(state.font_history(font).await.unwrap().len(), history.len());
+        for version_glyph in &history[0].glyphs {
+            state.glyph_ancestry(id(version_glyph.glyph)).await.unwrap();
+        }
+        // The queue still works
+        state.evolve_offline(font, 1).await.unwrap();
+    });
+}
+
+#[test]
+fn garbage_collection_keeps_tested_glyphs() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        finish_version(&state, font, user).await;
+        finish_version(&state, font, user).await;
+        assert_eq!(state.font_history(font).await.unwrap().len(), 3);
+
+        // Candidates that lost are only reachable from their trials
+        state.collect_garbage(false).await.unwrap();
+        assert!(state.verify(false).await.unwrap().problems.is_empty());
+    });
+}
+
+#[test]
+fn garbage_collection_keeps_imported_glyphs() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let glyph_ids = state.import_glyphs(&[glyph('a'), glyph('b')]).await.unwrap();
+
+        let report = state.collect_garbage(false).await.unwrap();
+        assert_eq!(report.glyphs, 0);
+        let font = state.add_font_with_glyphs(&glyph_ids, TestKind::Single).await.unwrap();
+        assert_eq!(state.font_history(font).await.unwrap()[0].glyphs.len(), 2);
+    });
+}
+
+#[test]
+fn garbage_collection_keeps_versions_forked_meanwhile() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        state.evolve_offline(font, 2).await.unwrap();
+        let mut history = state.font_history(font).await.unwrap();
+        let abandoned = history.remove(0);
+        state.roll_back_font(font, id(history.pop().unwrap().id)).await.unwrap();
+
+        let garbage = state.find_garbage().await.unwrap();
+        let fork = state.fork_font(id(abandoned.id), TestKind::Single).await.unwrap();
+        let mut report = GarbageReport::default();
+        assert!(!state.delete_garbage(&garbage, &mut report).await.unwrap());
+        assert_eq!((report.versions, report.glyphs), (0, 0));
+
+        // Collecting again finds the garbage with the fork in mind
+        let report = state.collect_garbage(false).await.unwrap();
+        assert_eq!(report.versions, 0);
+        assert!(state.verify(false).await.unwrap().problems.is_empty());
+        let forked = state.font_history(fork).await.unwrap();
+        assert_eq!(forked[0].parent, Some(abandoned.id));
+        state.glyph_ancestry(id(forked[0].glyphs[0].glyph)).await.unwrap();
+    });
+}
+
+#[test]
+fn expired_tests_requeue_their_candidate() {
+    task::block_on(async {
+        let (fitness, mut worker) = quick_config();
+        worker.test_lifetime = Duration::from_millis(200);
+        worker.expiry = Expiry::Requeue;
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+
+        state.add_next_test(font, user).await.unwrap();
+        let shown = shown_glyph(state.get_test(user).await.unwrap());
+        task::sleep(Duration::from_millis(300)).await;
+        assert!(state.get_test(user).await.unwrap().is_none());
+        state.expire_tests().await.unwrap();
+
+        state.add_next_test(font, user).await.unwrap();
+        assert_eq!(shown_glyph(state.get_test(user).await.unwrap()), shown);
+    });
+}
+
+#[test]
+fn expired_tests_discard_their_candidate() {
+    task::block_on(async {
+        let (fitness, mut worker) = quick_config();
+        worker.test_lifetime = Duration::from_millis(200);
+        worker.expiry = Expiry::Discard;
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        let queued = state.font_candidates(font).await.unwrap().len();
+
+        state.add_next_test(font, user).await.unwrap();
+        let shown = shown_glyph(state.get_test(user).await.unwrap());
+        task::sleep(Duration::from_millis(300)).await;
+        state.expire_tests().await.unwrap();
+        let candidates = state.font_candidates(font).await.unwrap();
+        assert_eq!(candidates.iter().filter(|candidate| candidate.queued).count(), queued - 1);
+
+        // The other candidates are shown before the version ends
+        for _ in 0..2 * (queued - 1) {
+            state.add_next_test(font, user).await.unwrap();
+            assert_ne!(shown_glyph(state.get_test(user).await.unwrap()), shown);
+            state.submit_keystroke(user, Keystroke { char: 'a', time: 100.0 }).await.unwrap();
+        }
+        assert_eq!(state.font_history(font).await.unwrap().len(), 1);
+        state.add_next_test(font, user).await.unwrap();
+        assert_eq!(state.font_history(font).await.unwrap().len(), 2);
+        assert!(state.get_test(user).await.unwrap().is_some());
+    });
+}
+
+#[test]
+fn queues_are_filled_up_to_their_depth() {
+    task::block_on(async {
+        let (fitness, mut worker) = quick_config();
+        worker.min_candidates = 4;
+        worker.queue_depth = 2;
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        let first_version = state.font_history(font).await.unwrap()[0].id;
+        let queued = |candidates: Vec<CandidateSummary>| candidates.iter().filter(|candidate| candidate.queued).count();
+
+        // Two variants and the glyph itself
+        assert_eq!(queued(state.font_candidates(font).await.unwrap()), 3);
+        state.fill_queues().await.unwrap();
+        assert_eq!(queued(state.font_candidates(font).await.unwrap()), 3);
+
+        let mut shown = BTreeSet::new();
+        while queued(state.font_candidates(font).await.unwrap()) >= 2 {
+            state.add_next_test(font, user).await.unwrap();
+            shown.insert(shown_glyph(state.get_test(user).await.unwrap()));
+            state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+        }
+        state.fill_queues().await.unwrap();
+        assert_eq!(queued(state.font_candidates(font).await.unwrap()), 2);
+
+        // Without the worker, an empty queue is filled by the next test, until every candidate was shown
+        loop {
+            state.add_next_test(font, user).await.unwrap();
+            if state.font_history(font).await.unwrap()[0].id != first_version {
+                break;
+            }
+            shown.insert(shown_glyph(state.get_test(user).await.unwrap()));
+            state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+        }
+        assert_eq!(shown.len(), 5);
+    });
+}
+
+#[test]
+fn undecodable_tests_are_removed() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let db = Database::in_memory();
+        let (handle, _) = worker::channel(worker);
+        let state = State::new(db.clone(), fitness, evaluator::Config::default(), handle, HashParams::default()).await.unwrap();
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+
+        // Too short to be a test, like tests saved in an older layout
+        let active_tests: Tree<Id<Glyph>, Id<User>> = db.tree(b"test_sessions").await.unwrap();
+        active_tests.insert_with_key(user, &id(1)).await.unwrap();
+        assert!(state.get_test(user).await.unwrap().is_none());
+        assert!(state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.is_ok());
+        state.expire_tests().await.unwrap();
+        assert!(active_tests.get_option(user).await.unwrap().is_none());
+
+        state.add_next_test(font, user).await.unwrap();
+        assert!(state.get_test(user).await.unwrap().is_some());
+    });
+}
+
+#[test]
+fn reset_scores_and_delete_users() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        let queued = state.font_candidates(font).await.unwrap().len();
+
+        // Candidates are tested in turn, so the first one has its second trial after the others have one
+        for _ in 0..=queued {
+            state.add_next_test(font, user).await.unwrap();
+            state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+        }
+        let candidates = state.font_candidates(font).await.unwrap();
+        assert_eq!(candidates.iter().filter(|candidate| candidate.queued).count(), queued - 1);
+        assert_eq!(candidates.iter().map(|candidate| candidate.trials).sum::<u32>(), queued as u32 + 1);
+        assert!(candidates.iter().all(|candidate| candidate.median_time == (candidate.trials > 0).then_some(500.0)));
+
+        assert_eq!(state.reset_scores(font).await.unwrap(), 1);
+        let candidates = state.font_candidates(font).await.unwrap();
+        assert!(candidates.iter().all(|candidate| candidate.queued && candidate.trials == 0));
+
+        state.add_next_test(font, user).await.unwrap();
+        state.delete_user("ann").await.unwrap();
+        assert!(state.log_in("ann", "password").await.is_err());
+        assert!(state.user_name(user).await.is_err());
+        // The deleted user's test is requeued
+        assert!(state.font_candidates(font).await.unwrap().iter().all(|candidate| candidate.queued));
+        assert!(state.delete_user("ann").await.is_err());
+    });
+}
+
+#[test]
+fn verify_reports_and_repairs_problems() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let db = Database::in_memory();
+        let (worker, _) = worker::channel(worker);
+        let state = State::new(db.clone(), fitness, evaluator::Config::default(), worker, HashParams::default()).await.unwrap();
+        let font = state.add_font(vec![glyph('a'), glyph('b')], TestKind::Single).await.unwrap();
+        assert!(state.verify(false).await.unwrap().problems.is_empty());
+
+        // A queued candidate disappears, which leaves its origin behind too
+        let candidate = state.font_candidates(font).await.unwrap().into_iter().find(|candidate| candidate.parent.is_some()).unwrap();
+        let glyphs: Tree<Glyph> = db.tree(b"glyphs").await.unwrap();
+        glyphs.remove(id(candidate.glyph)).await.unwrap();
+        // Too short to be a font
+        let fonts: Tree<Id<Glyph>, Id<Font>> = db.tree(b"fonts").await.unwrap();
+        fonts.insert_with_key(id(u64::MAX), &id(1)).await.unwrap();
+
+        let report = state.verify(false).await.unwrap();
+        let mut trees: Vec<&str> = report.problems.iter().map(|problem| problem.tree.as_str()).collect();
+        trees.sort_unstable();
+        assert_eq!(trees, ["fonts", "fonts", "glyph_origins"]);
+        assert!(report.problems.iter().all(|problem| !problem.repaired));
+
+        let report = state.verify(true).await.unwrap();
+        assert_eq!(report.problems.len(), 3);
+        assert!(report.problems.iter().all(|problem| problem.repaired));
+        assert!(state.verify(false).await.unwrap().problems.is_empty());
+        assert!(state.font_candidates(font).await.unwrap().iter().all(|other| other.glyph != candidate.glyph));
+    });
+}
+
+#[test]
+fn changed_password_costs_keep_old_passwords() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let db = Database::in_memory();
+        let (handle, _) = worker::channel(worker);
+        let state = State::new(db.clone(), fitness, evaluator::Config::default(), handle, HashParams::default()).await.unwrap();
+        let user = state.add_user("ann", "password").await.unwrap();
+
+        let cheaper = HashParams {
+            memory_cost: 8,
+            time_cost: 1,
+            lanes: 1,
+        };
+        let (handle, _) = worker::channel(worker);
+        let state = State::new(db, fitness, evaluator::Config::default(), handle, cheaper).await.unwrap();
+        assert!(state.log_in("ann", "wrong").await.is_err());
+        // The first login hashes the password again with the new costs
+        for _ in 0..2 {
+            assert_eq!(state.log_in("ann", "password").await.unwrap(), user);
+        }
+    });
+}
+
+#[test]
+fn metrics_count_tests_and_generations() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+
+        state.add_next_test(font, user).await.unwrap();
+        state.submit_keystroke(user, Keystroke { char: 'a', time: 500.0 }).await.unwrap();
+        state.evolve_offline(font, 2).await.unwrap();
+
+        let metrics = state.render_metrics().await.unwrap();
+        let lines: Vec<&str> = metrics.lines().collect();
+        for expected in [
+            "fontgen_tests_served_total{kind=\"single\"} 1".to_owned(),
+            "fontgen_tests_submitted_total{kind=\"single\",result=\"correct\"} 1".to_owned(),
+            "fontgen_submission_latency_seconds_count{kind=\"single\"} 1".to_owned(),
+            "fontgen_submission_latency_seconds_bucket{kind=\"single\",le=\"+Inf\"} 1".to_owned(),
+            format!("fontgen_generations_total{{font=\"{}\"}} 2", font),
+            "fontgen_tree_items{tree=\"fonts\"} 1".to_owned(),
+        ] {
+            assert!(lines.contains(&expected.as_str()), "missing {:?} in:\n{}", expected, metrics);
+        }
+        let queue_depth = format!("fontgen_candidate_queue_depth{{font=\"{}\"}} ", font);
+        assert!(lines.iter().any(|line| line.starts_with(&queue_depth)), "{}", metrics);
+    });
+}
+
+#[test]
+fn watchers_get_versions_best_scores_and_drained_queues() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let user = state.add_user("ann", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+        assert!(state.watch_font(id(u64::from(font) + 1)).await.is_err());
+
+        let (current, events) = state.watch_font(font).await.unwrap();
+        let first_version = match current {
+            FontEvent::Version { version, glyphs, .. } => {
+                assert_eq!(glyphs.len(), 1);
+                assert_eq!(glyphs[0].char, 'a');
+                assert!(!glyphs[0].outline.is_empty());
+                version
+            },
+            event => panic!("expected the current version, got {:?}", event),
+        };
+
+        finish_version(&state, font, user).await;
+
+        let mut best_scores = Vec::new();
+        let mut drained = false;
+        let mut next_version = None;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                FontEvent::BestScore { version, glyph } => {
+                    assert_eq!((version, glyph.char), (first_version, 'a'));
+                    assert!(glyph.fitness.is_some());
+                    assert!(!drained, "best score after the queue was drained");
+                    // Only changes are published
+                    assert_ne!(best_scores.last(), Some(&(glyph.glyph, glyph.fitness)));
+                    best_scores.push((glyph.glyph, glyph.fitness));
+                },
+                FontEvent::QueueDrained { version } => {
+                    assert_eq!(version, first_version);
+                    drained = true;
+                },
+                FontEvent::Version { version, parent, .. } => {
+                    assert!(drained, "new version before the queue was drained");
+                    assert_eq!(parent, Some(first_version));
+                    next_version = Some(version);
+                },
+            }
+        }
+        assert!(!best_scores.is_empty());
+        assert_eq!(next_version, Some(state.font_history(font).await.unwrap()[0].id));
+    });
+}
+
+#[test]
+fn leaderboards_rank_testers() {
+    task::block_on(async {
+        let (fitness, worker) = quick_config();
+        let state = new_state(fitness, worker).await;
+        let ann = state.add_user("ann", "password").await.unwrap();
+        let bob = state.add_user("bob", "password").await.unwrap();
+        let font = state.add_font(vec![glyph('a')], TestKind::Single).await.unwrap();
+
+        // Ann's times decide the first version's winner
+        finish_version(&state, font, ann).await;
+
+        // Bob fails a test and passes another one
+        state.add_next_test(font, bob).await.unwrap();
+        while state.get_test(bob).await.unwrap().is_some() {
+            state.submit_keystroke(bob, Keystroke { char: 'b', time: 500.0 }).await.unwrap();
+        }
+        state.add_next_test(font, bob).await.unwrap();
+        state.submit_keystroke(bob, Keystroke { char: 'a', time: 700.0 }).await.unwrap();
+
+        for font_id in [Some(font), None] {
+            let leaderboard = state.leaderboard(font_id, 10).await.unwrap();
+            assert_eq!(leaderboard.font, font_id.map(u64::from));
+            let names: Vec<&str> = leaderboard.entries.iter().map(|entry| entry.name.as_str()).collect();
+            assert_eq!(names, ["ann", "bob"]);
+            let [ann_stats, bob_stats] = [&leaderboard.entries[0].stats, &leaderboard.entries[1].stats];
+            assert_eq!((ann_stats.winning_scores, ann_stats.accuracy, ann_stats.average_time), (1, Some(1.0), Some(500.0)));
+            assert_eq!((bob_stats.tests, bob_stats.winning_scores, bob_stats.accuracy, bob_stats.average_time), (2, 0, Some(0.5), Some(700.0)));
+        }
+        assert_eq!(state.leaderboard(None, 1).await.unwrap().entries.len(), 1);
+
+        let stats = state.user_stats(ann).await.unwrap();
+        assert_eq!(stats.name, "ann");
+        assert_eq!(stats.overall.winning_scores, 1);
+        assert_eq!(stats.fonts.len(), 1);
+        assert_eq!(stats.fonts[0].font, u64::from(font));
+        assert_eq!(stats.fonts[0].stats.tests, stats.overall.tests);
+
+        // Winning scores are counted when versions end, so abandoning the version doesn't take them back
+        let first_version = state.font_history(font).await.unwrap().pop().unwrap();
+        state.roll_back_font(font, id(first_version.id)).await.unwrap();
+        assert_eq!(state.user_stats(ann).await.unwrap().overall.winning_scores, 1);
+
+        state.delete_user("bob").await.unwrap();
+        assert!(state.user_stats(bob).await.is_err());
+        let names: Vec<String> = state.leaderboard(None, 10).await.unwrap().entries.into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["ann"]);
+        assert!(state.verify(false).await.unwrap().problems.is_empty());
+    });
+}
@@ -0,0 +1,50 @@
+use async_std::task;
+use backend::database::{Database};
+use backend::rate_limit::{Config, Key, Limiter};
+
+/// Slow enough that no tokens are added back while a test runs
+fn config(persist: bool) -> Config {
+    Config {
+        burst: 2.0,
+        per_second: 0.001,
+        persist,
+    }
+}
+
+#[test]
+fn buckets_run_out_after_the_burst() {
+    task::block_on(async {
+        let limiter = Limiter::new(config(false), &Database::in_memory()).await.unwrap();
+        let address = Key::address("127.0.0.1:5000");
+        let user = Key::user("1".parse().unwrap());
+
+        assert!(limiter.take(&[address.clone(), user.clone()]).await.unwrap().is_ok());
+        assert!(limiter.take(&[address.clone(), user.clone()]).await.unwrap().is_ok());
+        let retry_after = limiter.take(&[address.clone(), user.clone()]).await.unwrap().unwrap_err();
+        assert!(retry_after.as_secs() > 100);
+
+        // Other ports of the same address share its bucket
+        assert!(limiter.take(&[Key::address("127.0.0.1:6000")]).await.unwrap().is_err());
+        // Nothing is taken from the other buckets when one is empty
+        let other_user = Key::user("2".parse().unwrap());
+        assert!(limiter.take(&[address, other_user.clone()]).await.unwrap().is_err());
+        assert!(limiter.take(&[Key::address("10.0.0.1:5000"), other_user.clone()]).await.unwrap().is_ok());
+        assert!(limiter.take(&[other_user]).await.unwrap().is_ok());
+    });
+}
+
+#[test]
+fn persisted_buckets_survive_restarts() {
+    task::block_on(async {
+        let db = Database::in_memory();
+        let keys = [Key::user("1".parse().unwrap())];
+        let limiter = Limiter::new(config(true), &db).await.unwrap();
+        assert!(limiter.take(&keys).await.unwrap().is_ok());
+        assert!(limiter.take(&keys).await.unwrap().is_ok());
+
+        let restarted = Limiter::new(config(true), &db).await.unwrap();
+        assert!(restarted.take(&keys).await.unwrap().is_err());
+        let forgetful = Limiter::new(config(false), &db).await.unwrap();
+        assert!(forgetful.take(&keys).await.unwrap().is_ok());
+    });
+}
@@ -0,0 +1,54 @@
+use crate::error::{ApiError};
+use serde::{Serialize};
+use serde::de::{DeserializeOwned};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{JsFuture};
+use web_sys::{RequestInit};
+
+/// Fetches a JSON response from the backend
+pub async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, ApiError> {
+    let window = web_sys::window().ok_or(ApiError::NoWindow)?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+        .await?
+        .dyn_into()?;
+
+    let text = read_text(&response).await?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Sends `body` as JSON with the method, or no body if it's `None`. Returns `None` if the response has no content.
+pub async fn send_json<B: Serialize, T: DeserializeOwned>(method: &str, url: &str, body: Option<&B>) -> Result<Option<T>, ApiError> {
+    let window = web_sys::window().ok_or(ApiError::NoWindow)?;
+    let mut init = RequestInit::new();
+    init.method(method);
+    if let Some(body) = body {
+        init.body(Some(&JsValue::from_str(&serde_json::to_string(body)?)));
+    }
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str_and_init(url, &init))
+        .await?
+        .dyn_into()?;
+
+    let text = read_text(&response).await?;
+    if text.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&text)?))
+}
+
+/// Returns the response's body, or the error that it describes
+async fn read_text(response: &web_sys::Response) -> Result<String, ApiError> {
+    let text = JsFuture::from(response.text()?)
+        .await?
+        .as_string()
+        .unwrap_or_default();
+
+    if !response.ok() {
+        // Responses that don't come from the backend, such as from a proxy, might not have an `ErrorResponse`
+        return Err(match serde_json::from_str(&text) {
+            Ok(error) => ApiError::Server(error),
+            Err(_) => ApiError::Status(response.status()),
+        });
+    }
+
+    Ok(text)
+}
@@ -0,0 +1,99 @@
+use crate::api;
+use crate::error::{ApiError};
+use shared::report::{ConfusionMatrix};
+use sycamore::prelude::*;
+
+/// Shows how often each character was typed for each glyph in a font version, as a heat map
+#[component]
+pub fn Body<G: Html>(cx: Scope, version: u64) -> View<G> {
+    let matrix = create_signal(cx, None::<Result<ConfusionMatrix, ApiError>>);
+
+    sycamore::futures::spawn_local_scoped(cx, async move {
+        let url = format!("/api/font-versions/{}/confusions.json", version);
+        matrix.set(Some(api::get_json(&url).await));
+    });
+
+    let csv_url = format!("/api/font-versions/{}/confusions.csv", version);
+
+    view! { cx,
+        div(class="box row gap") {
+            h2 {
+                "Confusion matrix"
+            }
+            a(href=csv_url, rel="external", download="") {
+                "Download CSV"
+            }
+        }
+        div(class="box scroll fill") {
+            (match *matrix.get() {
+                None => view! { cx,
+                    "Loading..."
+                },
+                Some(Err(ref error)) => {
+                    let message = format!("Error: {}", error);
+                    view! { cx,
+                        (message)
+                    }
+                },
+                Some(Ok(ref matrix)) => heat_map(cx, matrix),
+            })
+        }
+    }
+}
+
+/// Rows are shown characters and columns are typed characters. Each cell's shade is its share of the row's tests.
+fn heat_map<G: Html>(cx: Scope, matrix: &ConfusionMatrix) -> View<G> {
+    let header = View::new_fragment(
+        matrix.chars.iter()
+            .map(|&char| view! { cx,
+                th {
+                    (char)
+                }
+            })
+            .collect()
+    );
+
+    let rows = View::new_fragment(
+        matrix.chars.iter()
+            .zip(&matrix.counts)
+            .map(|(&char, counts)| {
+                let total = std::cmp::max(1, counts.iter().sum::<u32>());
+                let cells = View::new_fragment(
+                    counts.iter()
+                        .map(|&count| {
+                            let style = format!(
+                                "background: rgba(255, 64, 0, {:.3})",
+                                f64::from(count) / f64::from(total),
+                            );
+                            view! { cx,
+                                td(style=style) {
+                                    (count)
+                                }
+                            }
+                        })
+                        .collect()
+                );
+                view! { cx,
+                    tr {
+                        th {
+                            (char)
+                        }
+                        (cells)
+                    }
+                }
+            })
+            .collect()
+    );
+
+    view! { cx,
+        table(class="heat-map") {
+            tr {
+                th {
+                    "Shown \\ Typed"
+                }
+                (header)
+            }
+            (rows)
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use shared::error::{ErrorCode, ErrorResponse};
 use std::fmt::{self, Display, Formatter}; // includes `Display::fmt` method
 
 #[derive(Debug)]
@@ -26,3 +27,57 @@ impl Display for DbError {
         }
     }
 }
+
+#[derive(Debug)]
+pub enum ApiError {
+    Js(wasm_bindgen::JsValue),
+    Json(serde_json::Error),
+    NoWindow,
+    /// An error response from the backend
+    Server(ErrorResponse),
+    Status(u16),
+}
+
+impl From<wasm_bindgen::JsValue> for ApiError {
+    fn from(error: wasm_bindgen::JsValue) -> Self {
+        ApiError::Js(error)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(error: serde_json::Error) -> Self {
+        ApiError::Json(error)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ApiError::Js(error) => format!("{:?}", error).fmt(f),
+            ApiError::Json(_) => "the server sent invalid data".fmt(f),
+            ApiError::NoWindow => "the page isn't running in a browser window".fmt(f),
+            ApiError::Server(error) => describe(error.code).fmt(f),
+            ApiError::Status(status) => format!("the server responded with status {}", status).fmt(f),
+        }
+    }
+}
+
+/// What to tell the user. The backend's message is only for debugging, so it isn't shown.
+fn describe(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::NotLoggedIn => "you need to log in first",
+        ErrorCode::InvalidLogin => "the name or password is incorrect",
+        ErrorCode::NotOperator => "only the server's operators can do that",
+        ErrorCode::AddressNotAllowed => "that can't be done from your address",
+        ErrorCode::NotFound => "it doesn't exist, or it was deleted",
+        ErrorCode::NotInHistory => "that version isn't in the font's history",
+        ErrorCode::InvalidRequest => "the request was invalid",
+        ErrorCode::InvalidBackup => "the backup is damaged or from a newer server",
+        ErrorCode::NameTaken => "that name is already taken",
+        ErrorCode::DatabaseNotEmpty => "backups can only be restored into an empty database",
+        ErrorCode::RateLimited => "you're going too fast, so wait a moment and try again",
+        ErrorCode::Storage => "the server couldn't read or save its data",
+        ErrorCode::Decode => "the server's data is damaged",
+        ErrorCode::Internal => "something went wrong on the server",
+    }
+}
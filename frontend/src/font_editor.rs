@@ -1,5 +1,7 @@
 mod glyph_list;
 mod state;
+pub mod live;
+pub mod version_diff;
 
 use self::glyph_list::{GlyphList};
 use self::state::{State};
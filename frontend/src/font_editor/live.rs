@@ -0,0 +1,154 @@
+use crate::error::{ApiError};
+use shared::event::{FontEvent, LiveGlyph};
+use std::collections::{BTreeMap};
+use sycamore::prelude::*;
+use wasm_bindgen::{JsCast};
+use wasm_bindgen::closure::{Closure};
+use web_sys::{EventSource, MessageEvent};
+
+/// The event names that `FontEvent::name` gives
+const EVENT_NAMES: [&str; 3] = ["version", "best_score", "queue_drained"];
+
+#[derive(Clone)]
+struct LiveChar {
+    current: LiveGlyph,
+    /// The tested candidate with the best fitness so far, which replaces `current` in the next version
+    best: Option<LiveGlyph>,
+}
+
+/// Signals that the font's events change. They're `RcSignal`s because the `EventSource` listeners outlive the scope's borrows.
+#[derive(Clone)]
+struct Live {
+    version: RcSignal<Option<u64>>,
+    chars: RcSignal<BTreeMap<char, LiveChar>>,
+    status: RcSignal<String>,
+}
+
+impl Live {
+    fn apply(&self, event: FontEvent) {
+        match event {
+            FontEvent::Version { version, glyphs, .. } => {
+                self.version.set(Some(version));
+                self.chars.set(
+                    glyphs
+                        .into_iter()
+                        .map(|glyph| (glyph.char, LiveChar { current: glyph, best: None }))
+                        .collect()
+                );
+                self.status.set(format!("Testing version {}", version));
+            },
+            // Events of an earlier version can arrive after a reconnection
+            FontEvent::BestScore { version, glyph } if *self.version.get() == Some(version) => {
+                if let Some(char) = self.chars.modify().get_mut(&glyph.char) {
+                    char.best = Some(glyph);
+                }
+            },
+            FontEvent::QueueDrained { version } if *self.version.get() == Some(version) => {
+                self.status.set(format!("Version {} is finished, and the next version starts soon", version));
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Shows the glyphs of a font's current version and the best candidate for each character, updated by the font's events while testers work on it
+#[component]
+pub fn Body<G: Html>(cx: Scope, font: u64) -> View<G> {
+    let live = Live {
+        version: create_rc_signal(None),
+        chars: create_rc_signal(BTreeMap::new()),
+        status: create_rc_signal("Connecting...".to_owned()),
+    };
+    let title = format!("Font {}", font);
+
+    match EventSource::new(&format!("/api/fonts/{}/events", font)) {
+        Ok(source) => {
+            let mut listeners = Vec::new();
+            for name in EVENT_NAMES {
+                let live = live.clone();
+                let listener = Closure::<dyn FnMut(MessageEvent)>::new(move |message: MessageEvent| {
+                    let data = message.data().as_string().unwrap_or_default();
+                    match serde_json::from_str(&data) {
+                        Ok(event) => live.apply(event),
+                        Err(error) => live.status.set(format!("Error: {}", ApiError::from(error))),
+                    }
+                });
+                let _ = source.add_event_listener_with_callback(name, listener.as_ref().unchecked_ref());
+                listeners.push(listener);
+            }
+
+            // Browsers reconnect by themselves, unless the response was an error such as an unknown font
+            let on_error = {
+                let (source, status) = (source.clone(), live.status.clone());
+                Closure::<dyn FnMut()>::new(move || status.set(match source.ready_state() {
+                    EventSource::CLOSED => "Error: the font's events aren't available".to_owned(),
+                    _ => "Disconnected, reconnecting...".to_owned(),
+                }))
+            };
+            source.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+            on_cleanup(cx, move || {
+                source.close();
+                drop((listeners, on_error));
+            });
+        },
+        Err(error) => live.status.set(format!("Error: {}", ApiError::from(error))),
+    }
+
+    let Live { chars, status, .. } = live;
+    view! { cx,
+        div(class="box row gap") {
+            h2 {
+                (title)
+            }
+            (status.get())
+        }
+        div(class="box col gap scroll fill") {
+            (View::new_fragment(
+                chars.get().values()
+                    .map(|char| char_row(cx, char))
+                    .collect()
+            ))
+        }
+    }
+}
+
+fn char_row<G: Html>(cx: Scope, char: &LiveChar) -> View<G> {
+    let fitness = |glyph: &LiveGlyph| match glyph.fitness {
+        Some(fitness) => format!("{:.1}", fitness),
+        None => "none".to_owned(),
+    };
+    let current_outline = char.current.outline.clone();
+    let summary = match &char.best {
+        Some(best) => format!(
+            "{}: fitness {}, best candidate {} with fitness {}",
+            char.current.char,
+            fitness(&char.current),
+            best.glyph,
+            fitness(best),
+        ),
+        None => format!("{}: fitness {}, no candidate scored yet", char.current.char, fitness(&char.current)),
+    };
+    let best = char.best.as_ref().map(|best| {
+        let outline = best.outline.clone();
+        view! { cx,
+            div(class="thumbnail") {
+                svg(xmlns="http://www.w3.org/2000/svg", viewBox="0 0 32767 32767") {
+                    path(class="new-outline", fill-rule="evenodd", d=outline)
+                }
+            }
+        }
+    }).unwrap_or_else(View::empty);
+
+    view! { cx,
+        div(class="row gap") {
+            div(class="thumbnail") {
+                svg(xmlns="http://www.w3.org/2000/svg", viewBox="0 0 32767 32767") {
+                    path(fill-rule="evenodd", d=current_outline)
+                }
+            }
+            (best)
+            (summary)
+        }
+    }
+}
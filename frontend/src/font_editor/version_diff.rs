@@ -0,0 +1,90 @@
+use crate::api;
+use crate::error::{ApiError};
+use shared::diff::{CharDiff, DiffSide, VersionDiff};
+use sycamore::prelude::*;
+
+pub struct Versions {
+    pub old: u64,
+    pub new: u64,
+}
+
+/// Shows each changed character with the old and new outlines on top of each other
+#[component]
+pub fn Body<G: Html>(cx: Scope, versions: Versions) -> View<G> {
+    let diff = create_signal(cx, None::<Result<VersionDiff, ApiError>>);
+    let title = format!("Changes from version {} to {}", versions.old, versions.new);
+
+    sycamore::futures::spawn_local_scoped(cx, async move {
+        let url = format!("/api/font-versions/{}/diff/{}", versions.old, versions.new);
+        diff.set(Some(api::get_json(&url).await));
+    });
+
+    view! { cx,
+        div(class="box row gap") {
+            h2 {
+                (title)
+            }
+            span(class="old-outline") {
+                "Old"
+            }
+            span(class="new-outline") {
+                "New"
+            }
+        }
+        div(class="box col gap scroll fill") {
+            (match *diff.get() {
+                None => view! { cx,
+                    "Loading..."
+                },
+                Some(Err(ref error)) => {
+                    let message = format!("Error: {}", error);
+                    view! { cx,
+                        (message)
+                    }
+                },
+                Some(Ok(ref diff)) if diff.chars.is_empty() => view! { cx,
+                    "No characters changed."
+                },
+                Some(Ok(ref diff)) => View::new_fragment(
+                    diff.chars.iter()
+                        .map(|char_diff| char_row(cx, char_diff))
+                        .collect()
+                ),
+            })
+        }
+    }
+}
+
+fn char_row<G: Html>(cx: Scope, char_diff: &CharDiff) -> View<G> {
+    let outline = |side: &Option<DiffSide>| side
+        .as_ref()
+        .map(|side| side.outline.clone())
+        .unwrap_or_default();
+    let old_outline = outline(&char_diff.old);
+    let new_outline = outline(&char_diff.new);
+
+    let fitness = |side: &Option<DiffSide>| match side.as_ref().and_then(|side| side.fitness) {
+        Some(fitness) => format!("{:.1}", fitness),
+        None => "none".to_owned(),
+    };
+    let summary = format!(
+        "{}: fitness {} \u{2192} {}, {} points changed{}",
+        char_diff.char,
+        fitness(&char_diff.old),
+        fitness(&char_diff.new),
+        char_diff.points.len(),
+        if char_diff.shape_changed { ", shape changed" } else { "" },
+    );
+
+    view! { cx,
+        div(class="row gap") {
+            div(class="thumbnail") {
+                svg(xmlns="http://www.w3.org/2000/svg", viewBox="0 0 32767 32767") {
+                    path(class="old-outline", fill-rule="evenodd", d=old_outline)
+                    path(class="new-outline", fill-rule="evenodd", d=new_outline)
+                }
+            }
+            (summary)
+        }
+    }
+}
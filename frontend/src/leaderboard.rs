@@ -0,0 +1,216 @@
+use crate::api;
+use crate::error::{ApiError};
+use shared::stats::{FontStats, Leaderboard, LeaderboardEntry, TesterStats, UserStats};
+use sycamore::prelude::*;
+
+/// Ranks testers in one font, or in every font if `font` is `None`
+#[component]
+pub fn Body<G: Html>(cx: Scope, font: Option<u64>) -> View<G> {
+    let leaderboard = create_signal(cx, None::<Result<Leaderboard, ApiError>>);
+    let title = match font {
+        Some(font) => format!("Leaderboard of font {}", font),
+        None => "Leaderboard".to_owned(),
+    };
+
+    sycamore::futures::spawn_local_scoped(cx, async move {
+        let url = match font {
+            Some(font) => format!("/api/fonts/{}/leaderboard", font),
+            None => "/api/leaderboard".to_owned(),
+        };
+        leaderboard.set(Some(api::get_json(&url).await));
+    });
+
+    view! { cx,
+        div(class="box row gap") {
+            h2 {
+                (title)
+            }
+        }
+        div(class="box scroll fill") {
+            (match *leaderboard.get() {
+                None => view! { cx,
+                    "Loading..."
+                },
+                Some(Err(ref error)) => {
+                    let message = format!("Error: {}", error);
+                    view! { cx,
+                        (message)
+                    }
+                },
+                Some(Ok(ref leaderboard)) if leaderboard.entries.is_empty() => view! { cx,
+                    "No one has finished a test yet."
+                },
+                Some(Ok(ref leaderboard)) => ranking(cx, &leaderboard.entries),
+            })
+        }
+    }
+}
+
+fn ranking<G: Html>(cx: Scope, entries: &[LeaderboardEntry]) -> View<G> {
+    let rows = View::new_fragment(
+        entries.iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let rank = index + 1;
+                let href = format!("/users/{}/stats", entry.user);
+                let name = entry.name.clone();
+                let cells = stats_cells(cx, &entry.stats);
+                view! { cx,
+                    tr {
+                        td {
+                            (rank)
+                        }
+                        td {
+                            a(href=href) {
+                                (name)
+                            }
+                        }
+                        (cells)
+                    }
+                }
+            })
+            .collect()
+    );
+
+    view! { cx,
+        table(class="stats-table") {
+            tr {
+                th {
+                    "Rank"
+                }
+                th {
+                    "Tester"
+                }
+                (stats_header(cx))
+            }
+            (rows)
+        }
+    }
+}
+
+/// Shows one tester's results in every font, and in each font they took part in
+#[component]
+pub fn Tester<G: Html>(cx: Scope, user: u64) -> View<G> {
+    let stats = create_signal(cx, None::<Result<UserStats, ApiError>>);
+
+    sycamore::futures::spawn_local_scoped(cx, async move {
+        let url = format!("/api/users/{}/stats", user);
+        stats.set(Some(api::get_json(&url).await));
+    });
+
+    view! { cx,
+        (match *stats.get() {
+            None => view! { cx,
+                div(class="box") {
+                    "Loading..."
+                }
+            },
+            Some(Err(ref error)) => {
+                let message = format!("Error: {}", error);
+                view! { cx,
+                    div(class="box") {
+                        (message)
+                    }
+                }
+            },
+            Some(Ok(ref stats)) => tester_stats(cx, stats),
+        })
+    }
+}
+
+fn tester_stats<G: Html>(cx: Scope, stats: &UserStats) -> View<G> {
+    let title = format!("Statistics of {}", stats.name);
+    let overall = stats_cells(cx, &stats.overall);
+    let fonts = View::new_fragment(
+        stats.fonts.iter()
+            .map(|FontStats { font, stats }| {
+                let href = format!("/fonts/{}/leaderboard", font);
+                let label = format!("Font {}", font);
+                let cells = stats_cells(cx, stats);
+                view! { cx,
+                    tr {
+                        td {
+                            a(href=href) {
+                                (label)
+                            }
+                        }
+                        (cells)
+                    }
+                }
+            })
+            .collect()
+    );
+
+    view! { cx,
+        div(class="box row gap") {
+            h2 {
+                (title)
+            }
+            a(href="/leaderboard") {
+                "Leaderboard"
+            }
+        }
+        div(class="box scroll fill") {
+            table(class="stats-table") {
+                tr {
+                    th {
+                        "Font"
+                    }
+                    (stats_header(cx))
+                }
+                tr {
+                    th {
+                        "All fonts"
+                    }
+                    (overall)
+                }
+                (fonts)
+            }
+        }
+    }
+}
+
+fn stats_header<G: Html>(cx: Scope) -> View<G> {
+    view! { cx,
+        th {
+            "Winning scores"
+        }
+        th {
+            "Tests"
+        }
+        th {
+            "Accuracy"
+        }
+        th {
+            "Average time"
+        }
+    }
+}
+
+fn stats_cells<G: Html>(cx: Scope, stats: &TesterStats) -> View<G> {
+    let winning_scores = stats.winning_scores;
+    let tests = stats.tests;
+    let accuracy = match stats.accuracy {
+        Some(accuracy) => format!("{:.0}%", accuracy * 100.0),
+        None => "none".to_owned(),
+    };
+    let average_time = match stats.average_time {
+        Some(time) => format!("{:.0} ms", time),
+        None => "none".to_owned(),
+    };
+
+    view! { cx,
+        td {
+            (winning_scores)
+        }
+        td {
+            (tests)
+        }
+        td {
+            (accuracy)
+        }
+        td {
+            (average_time)
+        }
+    }
+}
@@ -1,7 +1,11 @@
+mod api;
 mod char_input;
+mod confusion_matrix;
 mod error;
 mod font_editor;
 mod glyph_svg;
+mod leaderboard;
+mod typing_test;
 
 use sycamore::prelude::*;
 use sycamore_router::{HistoryIntegration, Route, Router, RouterProps};
@@ -11,6 +15,20 @@ use wasm_bindgen::prelude::{wasm_bindgen};
 enum PageRoute {
     #[to("/editor.html")]
     FontEditor,
+    #[to("/editor/fonts/<font>/live")]
+    LiveFont { font: u64 },
+    #[to("/editor/versions/<old>/diff/<new>")]
+    VersionDiff { old: u64, new: u64 },
+    #[to("/versions/<version>/confusions")]
+    ConfusionMatrix { version: u64 },
+    #[to("/leaderboard")]
+    Leaderboard,
+    #[to("/fonts/<font>/test")]
+    TypingTest { font: u64 },
+    #[to("/fonts/<font>/leaderboard")]
+    FontLeaderboard { font: u64 },
+    #[to("/users/<user>/stats")]
+    TesterStats { user: u64 },
     #[not_found]
     NotFound,
 }
@@ -29,6 +47,27 @@ pub fn init() {
                             PageRoute::FontEditor => view! { cx,
                                 font_editor::Body()
                             },
+                            PageRoute::LiveFont { font } => view! { cx,
+                                font_editor::live::Body(font)
+                            },
+                            PageRoute::VersionDiff { old, new } => view! { cx,
+                                font_editor::version_diff::Body(font_editor::version_diff::Versions { old, new })
+                            },
+                            PageRoute::ConfusionMatrix { version } => view! { cx,
+                                confusion_matrix::Body(version)
+                            },
+                            PageRoute::Leaderboard => view! { cx,
+                                leaderboard::Body(None)
+                            },
+                            PageRoute::TypingTest { font } => view! { cx,
+                                typing_test::Body(font)
+                            },
+                            PageRoute::FontLeaderboard { font } => view! { cx,
+                                leaderboard::Body(Some(font))
+                            },
+                            PageRoute::TesterStats { user } => view! { cx,
+                                leaderboard::Tester(user)
+                            },
                             PageRoute::NotFound => view! { cx,
                                 "The requested page does not exist."
                             },
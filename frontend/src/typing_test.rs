@@ -0,0 +1,192 @@
+use crate::api;
+use crate::char_input::{CharInput};
+use crate::error::{ApiError};
+use serde::{Serialize};
+use shared::presentation::{Presentation};
+use sycamore::prelude::*;
+
+#[derive(Serialize)]
+struct Keystroke {
+    char: char,
+    time: f64,
+}
+
+#[derive(Serialize)]
+struct Choice {
+    index: usize,
+    time: f64,
+}
+
+enum Status {
+    Loading,
+    Testing(Presentation),
+    /// Every candidate was tested enough, until the next version starts
+    QueueEmpty,
+    Failed(ApiError),
+}
+
+/// Milliseconds since the page was opened
+fn now() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or(0.0, |performance| performance.now())
+}
+
+/// Shows the font's tests one after another, in the way the font's test kind presents them
+#[component]
+pub fn Body<G: Html>(cx: Scope, font: u64) -> View<G> {
+    let status = create_signal(cx, Status::Loading);
+    // When the current test was shown, which keystroke and choice times count from
+    let shown = create_signal(cx, 0.0);
+    let title = format!("Testing font {}", font);
+
+    let next_test = move || sycamore::futures::spawn_local_scoped(cx, async move {
+        status.set(match api::send_json::<(), Presentation>("POST", &format!("/api/fonts/{}/test", font), None).await {
+            Ok(Some(presentation)) => {
+                shown.set(now());
+                Status::Testing(presentation)
+            },
+            Ok(None) => Status::QueueEmpty,
+            Err(error) => Status::Failed(error),
+        });
+    });
+
+    let type_char = move |char: char| {
+        // Multiple choice tests are answered by clicking
+        match *status.get() {
+            Status::Testing(Presentation::MultipleChoice { .. }) => return,
+            Status::Testing(_) => {},
+            _ => return,
+        }
+        let keystroke = Keystroke {
+            char,
+            time: now() - *shown.get(),
+        };
+        sycamore::futures::spawn_local_scoped(cx, async move {
+            if let Err(error) = api::send_json::<_, ()>("POST", "/api/test/keystroke", Some(&keystroke)).await {
+                status.set(Status::Failed(error));
+                return;
+            }
+            // Words need a keystroke for each glyph, and mistakes can be corrected until there are too many
+            match api::send_json::<(), Presentation>("GET", "/api/test", None).await {
+                Ok(Some(presentation)) => status.set(Status::Testing(presentation)),
+                Ok(None) => next_test(),
+                Err(error) => status.set(Status::Failed(error)),
+            }
+        });
+    };
+
+    let choose = move |index: usize| {
+        let choice = Choice {
+            index,
+            time: now() - *shown.get(),
+        };
+        sycamore::futures::spawn_local_scoped(cx, async move {
+            match api::send_json::<_, ()>("POST", "/api/test/choice", Some(&choice)).await {
+                Ok(_) => next_test(),
+                Err(error) => status.set(Status::Failed(error)),
+            }
+        });
+    };
+
+    next_test();
+
+    view! { cx,
+        div(class="box row gap") {
+            h2 {
+                (title)
+            }
+            label {
+                "Type here: "
+                CharInput(type_char)
+            }
+        }
+        div(class="box col gap fill") {
+            (match *status.get() {
+                Status::Loading => view! { cx,
+                    "Loading..."
+                },
+                Status::Testing(ref presentation) => presentation_view(cx, presentation, choose),
+                Status::QueueEmpty => view! { cx,
+                    "Every candidate has been tested for now. The next version starts soon, so try again in a moment."
+                },
+                Status::Failed(ref error) => {
+                    let message = format!("Error: {}", error);
+                    view! { cx,
+                        (message)
+                    }
+                },
+            })
+        }
+    }
+}
+
+fn presentation_view<'a, G: Html>(cx: Scope<'a>, presentation: &Presentation, choose: impl Fn(usize) + Copy + 'a) -> View<G> {
+    match presentation {
+        Presentation::Single { glyph } => view! { cx,
+            "Type the character:"
+            (test_glyph(cx, glyph, String::new()))
+        },
+        Presentation::Blurred { glyph, blur } => {
+            let style = format!("filter: blur(calc({} * var(--test-glyph-height)))", blur);
+            view! { cx,
+                "Type the character:"
+                (test_glyph(cx, glyph, style))
+            }
+        },
+        Presentation::Small { glyph, size } => {
+            let style = format!("height: {}px", size);
+            view! { cx,
+                "Type the character:"
+                (test_glyph(cx, glyph, style))
+            }
+        },
+        Presentation::Word { glyphs } => {
+            let glyphs = View::new_fragment(
+                glyphs.iter()
+                    .map(|glyph| test_glyph(cx, glyph, String::new()))
+                    .collect()
+            );
+            view! { cx,
+                "Type the word:"
+                div(class="row") {
+                    (glyphs)
+                }
+            }
+        },
+        Presentation::MultipleChoice { char, glyphs } => {
+            let prompt = format!("Choose the {:?}:", char);
+            let options = View::new_fragment(
+                glyphs.iter()
+                    .enumerate()
+                    .map(|(index, glyph)| {
+                        let glyph = test_glyph(cx, glyph, String::new());
+                        view! { cx,
+                            button(class="choice", on:click=move |_| choose(index)) {
+                                (glyph)
+                            }
+                        }
+                    })
+                    .collect()
+            );
+            view! { cx,
+                (prompt)
+                div(class="row gap") {
+                    (options)
+                }
+            }
+        },
+    }
+}
+
+/// `style` is added to the glyph's element, for the effects of some test kinds
+fn test_glyph<G: Html>(cx: Scope, outline: &str, style: String) -> View<G> {
+    let outline = outline.to_owned();
+    view! { cx,
+        div(class="test-glyph", style=style) {
+            svg(xmlns="http://www.w3.org/2000/svg", viewBox="0 0 32767 32767") {
+                path(fill-rule="evenodd", d=outline)
+            }
+        }
+    }
+}
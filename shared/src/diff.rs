@@ -0,0 +1,78 @@
+use crate::glyph::{Glyph};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{PI, TAU};
+
+/// The characters whose glyph or score is different in a newer font version
+#[derive(Serialize, Deserialize)]
+pub struct VersionDiff {
+    pub old_version: u64,
+    pub new_version: u64,
+    pub chars: Vec<CharDiff>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CharDiff {
+    pub char: char,
+    /// `None` if the old version doesn't have the character
+    pub old: Option<DiffSide>,
+    /// `None` if the new version doesn't have the character
+    pub new: Option<DiffSide>,
+    /// Points that moved or turned, matched by their index in both glyphs
+    pub points: Vec<PointDelta>,
+    /// True if the glyphs have different numbers of paths or points, so some points aren't in `points`
+    pub shape_changed: bool,
+}
+
+/// A character's glyph in one of the versions
+#[derive(Serialize, Deserialize)]
+pub struct DiffSide {
+    pub glyph: u64,
+    pub fitness: Option<f64>,
+    /// From `Glyph::to_svg_path_d`, for drawing the old and new outlines on top of each other
+    pub outline: String,
+}
+
+/// The new point's numbers minus the old point's numbers
+#[derive(Serialize, Deserialize)]
+pub struct PointDelta {
+    pub path: usize,
+    pub point: usize,
+    pub position: [i32; 2],
+    /// The shortest turn, from -π (exclusive) to π
+    pub radians: f32,
+    pub curviness: i32,
+}
+
+/// Returns the changed points and whether the shape changed, as in `CharDiff`.
+pub fn compare_glyphs(old: &Glyph, new: &Glyph) -> (Vec<PointDelta>, bool) {
+    let mut points = Vec::new();
+    let mut shape_changed = old.paths().len() != new.paths().len();
+
+    for (path, (old_path, new_path)) in old.paths().iter().zip(new.paths()).enumerate() {
+        shape_changed |= old_path.points().len() != new_path.points().len();
+        for (point, (old_point, new_point)) in old_path.points().iter().zip(new_path.points()).enumerate() {
+            if old_point == new_point {
+                continue;
+            }
+            points.push(PointDelta {
+                path,
+                point,
+                position: [0, 1].map(|axis| i32::from(new_point.position[axis]) - i32::from(old_point.position[axis])),
+                radians: wrap_radians(new_point.radians - old_point.radians),
+                curviness: i32::from(new_point.curviness) - i32::from(old_point.curviness),
+            });
+        }
+    }
+
+    (points, shape_changed)
+}
+
+/// Angles that differ by a full turn point the same way, so a point that turned across π is shown with a small turn instead of nearly a full one
+fn wrap_radians(radians: f32) -> f32 {
+    let wrapped = (radians + PI).rem_euclid(TAU) - PI;
+    if wrapped <= -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifies what went wrong in an error response. These names don't change when the messages do, so clients can rely on them.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request needs a session
+    NotLoggedIn,
+    /// The name or password is wrong
+    InvalidLogin,
+    /// The user isn't one of the server's operators
+    NotOperator,
+    /// The client's address isn't allowed to use the route, such as `/metrics`
+    AddressNotAllowed,
+    /// A user, font, version, glyph or other item doesn't exist
+    NotFound,
+    /// The version isn't in the font's history
+    NotInHistory,
+    /// The request's parameters or body are invalid
+    InvalidRequest,
+    /// The uploaded backup is damaged or from a newer server
+    InvalidBackup,
+    /// Another user has the name
+    NameTaken,
+    /// A backup can only be restored into an empty database
+    DatabaseNotEmpty,
+    /// The user or client address made too many requests, and must wait for the time in the `Retry-After` header
+    RateLimited,
+    /// The server couldn't read or write its data
+    Storage,
+    /// Stored data couldn't be decoded or encoded
+    Decode,
+    /// Any other problem on the server
+    Internal,
+}
+
+/// The body of every error response, as JSON
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    /// Details in English, for logs and debugging
+    pub message: String,
+}
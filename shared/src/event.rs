@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+/// Something that happened to a font. `GET /api/fonts/:font/events` sends these as server-sent events named by `FontEvent::name`, with the JSON as the data.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FontEvent {
+    /// The font has a new current version, from evolution or a rollback. Also sent first to each new subscriber.
+    Version {
+        version: u64,
+        parent: Option<u64>,
+        glyphs: Vec<LiveGlyph>,
+    },
+    /// A candidate that was just tested has the best fitness for its character in the current version. It wins if the version ends now.
+    BestScore {
+        version: u64,
+        glyph: LiveGlyph,
+    },
+    /// Every candidate of the version was tested enough, so the next version starts soon
+    QueueDrained {
+        version: u64,
+    },
+}
+
+impl FontEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FontEvent::Version { .. } => "version",
+            FontEvent::BestScore { .. } => "best_score",
+            FontEvent::QueueDrained { .. } => "queue_drained",
+        }
+    }
+}
+
+/// A glyph with its outline, so thumbnails can be drawn without another request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LiveGlyph {
+    pub char: char,
+    pub glyph: u64,
+    /// `None` if the glyph wasn't chosen by testers
+    pub fitness: Option<f64>,
+    /// From `Glyph::to_svg_path_d`
+    pub outline: String,
+}
@@ -1,5 +1,7 @@
 use crate::util::{char_map, char_write};
 use deku::prelude::*;
+use fastrand::{Rng};
+use serde::{Deserialize, Serialize};
 
 const X: usize = 0;
 const Y: usize = 1;
@@ -38,6 +40,26 @@ pub struct Point {
     pub curviness: i16,
 }
 
+/// The largest random change made to each number of a `Point` when a glyph is mutated
+#[derive(DekuRead, DekuWrite, Serialize, Deserialize, Clone, Copy)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian", ctx_default = "deku::ctx::Endian::Big")]
+pub struct Mutation {
+    pub position: i16,
+    /// The change is cubed, so small changes are more likely
+    pub radians: f32,
+    pub curviness: i16,
+}
+
+impl Default for Mutation {
+    fn default() -> Self {
+        Mutation {
+            position: 10,
+            radians: 0.1,
+            curviness: 10,
+        }
+    }
+}
+
 // `Glyph` must implement `Eq` to be used with `sycamore::flow::Keyed` because of lukechu10
 // https://github.com/sycamore-rs/sycamore/issues/452
 impl Eq for Point {}
@@ -45,7 +67,7 @@ impl Eq for Point {}
 impl Glyph {
     pub fn new(char: char) -> Self {
         Glyph {
-            char: char,
+            char,
             paths: vec![Path::new()],
         }
     }
@@ -54,9 +76,9 @@ impl Glyph {
         &self.paths
     }
 
-    pub fn mutate(&mut self) {
+    pub fn mutate(&mut self, mutation: &Mutation, rng: &Rng) {
         for path in &mut self.paths {
-            path.mutate();
+            path.mutate(mutation, rng);
         }
     }
 
@@ -84,20 +106,56 @@ impl Glyph {
         }
     }
 
-    pub fn generate_variants<'a, Iter>(old_glyphs: Iter) -> Vec<Glyph>
+    /// The variants only depend on the glyphs, `mutation` and the state of `rng`, so a seeded `Rng` reproduces them.
+    pub fn generate_variants<'a, Iter>(old_glyphs: Iter, mutation: &Mutation, rng: &Rng) -> Vec<Glyph>
     where
         Iter: Iterator<Item = &'a Glyph>,
     {
         let mut variants = Vec::<Glyph>::new();
         for old_glyph in old_glyphs {
             let mut glyph = old_glyph.clone();
-            glyph.mutate();
+            glyph.mutate(mutation, rng);
             variants.push(glyph);
         }
-        fastrand::shuffle(&mut variants);
+        rng.shuffle(&mut variants);
         variants
     }
 
+    /// Approximates each path as a polygon, using `segments` straight lines for each curve. The curves are the same as in `to_svg_path_d`.
+    pub fn to_polygons(&self, segments: u16) -> Vec<Vec<[f32; 2]>> {
+        fn to_f32(pair: [i16; 2]) -> [f32; 2] {
+            [f32::from(pair[X]), f32::from(pair[Y])]
+        }
+
+        self.paths
+            .iter()
+            .map(|path| {
+                let mut polygon = Vec::with_capacity(path.points.len() * usize::from(segments));
+                for (index, p0) in path.points.iter().enumerate() {
+                    let p1 = &path.points[(index + 1) % path.points.len()];
+                    let controls = [
+                        to_f32(p0.position),
+                        to_f32(p0.curve_point(p0.curviness)),
+                        to_f32(p1.curve_point(-p1.curviness)),
+                        to_f32(p1.position),
+                    ];
+                    for step in 0..segments {
+                        let t = f32::from(step) / f32::from(segments);
+                        let u = 1.0 - t;
+                        let weights = [u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t];
+                        let mut point = [0.0; 2];
+                        for (weight, control) in weights.iter().zip(&controls) {
+                            point[X] += weight * control[X];
+                            point[Y] += weight * control[Y];
+                        }
+                        polygon.push(point);
+                    }
+                }
+                polygon
+            })
+            .collect()
+    }
+
     /// Converts the glyph to a string for the `d` attribute in an SVG `path` element
     ///
     /// https://developer.mozilla.org/en-US/docs/Web/SVG/Attribute/d
@@ -148,6 +206,10 @@ impl Glyph {
 }
 
 impl Path {
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
     fn new() -> Self {
         let points: Vec<Point> =
             [
@@ -165,13 +227,13 @@ impl Path {
 
         Path {
             count: points.len() as u16,
-            points: points,
+            points,
         }
     }
 
-    fn mutate(&mut self) {
+    fn mutate(&mut self, mutation: &Mutation, rng: &Rng) {
         for point in &mut self.points {
-            point.mutate();
+            point.mutate(mutation, rng);
         }
     }
 
@@ -206,25 +268,25 @@ impl Point {
         ]
     }
 
-    fn mutate(&mut self) {
-        fn rand_between(min: f32, max: f32) -> f32 {
-            min + (fastrand::f32() * (max - min))
+    fn mutate(&mut self, mutation: &Mutation, rng: &Rng) {
+        fn rand_between(rng: &Rng, min: f32, max: f32) -> f32 {
+            min + (rng.f32() * (max - min))
         }
 
         /// Adds a random number between `-scale` and `scale`
-        fn mutate_float(num: &mut f32, scale: f32) {
-            *num += rand_between(-scale, scale).powi(3);
+        fn mutate_float(rng: &Rng, num: &mut f32, scale: f32) {
+            *num += rand_between(rng, -scale, scale).powi(3);
         }
 
         /// Adds a random integer between `-scale` and `scale`
-        fn mutate_int(num: &mut i16, scale: i16) {
-            let change_amount = fastrand::i16(-scale..=scale);
+        fn mutate_int(rng: &Rng, num: &mut i16, scale: i16) {
+            let change_amount = rng.i16(-scale..=scale);
             *num = std::cmp::max(0, num.saturating_add(change_amount));
         }
 
-        mutate_int(&mut self.position[X], 10);
-        mutate_int(&mut self.position[Y], 10);
-        mutate_float(&mut self.radians, 0.1);
-        mutate_int(&mut self.curviness, 10);
+        mutate_int(rng, &mut self.position[X], mutation.position);
+        mutate_int(rng, &mut self.position[Y], mutation.position);
+        mutate_float(rng, &mut self.radians, mutation.radians);
+        mutate_int(rng, &mut self.curviness, mutation.curviness);
     }
 }
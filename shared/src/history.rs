@@ -0,0 +1,51 @@
+use crate::glyph::{Mutation};
+use serde::{Deserialize, Serialize};
+
+/// One version of a font. A font's history is a list of these, from the current version back to the first.
+#[derive(Serialize, Deserialize)]
+pub struct VersionSummary {
+    pub id: u64,
+    /// `None` for the font's first version
+    pub parent: Option<u64>,
+    /// Milliseconds since the Unix epoch
+    pub created: u64,
+    /// A string, because JavaScript numbers can't hold every `u64`
+    pub seed: String,
+    pub mutation: Mutation,
+    /// The fewest candidates that the version was given
+    pub min_candidates: u32,
+    pub glyphs: Vec<VersionGlyphSummary>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VersionGlyphSummary {
+    pub char: char,
+    pub glyph: u64,
+    /// The fitness that made the glyph win, or `None` if it wasn't chosen by testers
+    pub fitness: Option<f64>,
+}
+
+/// One glyph in the list of glyphs that a glyph was mutated from, which starts with the glyph itself.
+#[derive(Serialize, Deserialize)]
+pub struct Ancestor {
+    pub glyph: u64,
+    pub char: char,
+    /// The version that the glyph was a candidate in, or `None` if it was added with a font
+    pub font_version: Option<u64>,
+}
+
+/// A glyph competing in a font's current version, for the admin tool.
+#[derive(Serialize, Deserialize)]
+pub struct CandidateSummary {
+    pub glyph: u64,
+    pub char: char,
+    /// The glyph it was mutated from, or `None` if it's one of the version's own glyphs
+    pub parent: Option<u64>,
+    /// Whether it's still waiting to be tested
+    pub queued: bool,
+    /// Tests where the correct character was typed
+    pub trials: u32,
+    pub errors: u32,
+    /// The median of the trials' normalized times, or `None` before the first correct trial
+    pub median_time: Option<f64>,
+}
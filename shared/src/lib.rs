@@ -1,4 +1,14 @@
+// The code generated by deku's derive macros doesn't use `div_ceil`
+#![allow(clippy::manual_div_ceil)]
+
+pub mod diff;
+pub mod error;
+pub mod event;
 pub mod glyph;
+pub mod history;
+pub mod presentation;
+pub mod report;
+pub mod stats;
 pub mod util;
 
 /// Implements `Clone` on a struct with a `phantom: PhantomData<T>` field, even if `T` doesn't.
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+/// What the frontend shows for a test. Glyphs are strings from `Glyph::to_svg_path_d`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Presentation {
+    /// Type the glyph's character
+    Single { glyph: String },
+    /// Choose which glyph is `char`, by sending its index
+    MultipleChoice { char: char, glyphs: Vec<String> },
+    /// Type the word spelled by the glyphs
+    Word { glyphs: Vec<String> },
+    /// Like `Single`, with a blur radius of `blur` times the glyph's height
+    Blurred { glyph: String, blur: f32 },
+    /// Like `Single`, with the glyph `size` CSS pixels tall
+    Small { glyph: String, size: u32 },
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+/// How often each character was typed when each character was shown, for every glyph tested in a font version.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ConfusionMatrix {
+    /// Labels for both the rows (shown characters) and the columns (typed characters)
+    pub chars: Vec<char>,
+    /// `counts[row][column]` is the number of times `chars[column]` was typed when `chars[row]` was shown. The diagonal counts tests where the correct character was typed first.
+    pub counts: Vec<Vec<u32>>,
+}
+
+impl ConfusionMatrix {
+    /// Builds the matrix from `(shown, typed, count)` items. Repeated pairs are added together.
+    pub fn from_counts(items: impl Iterator<Item = (char, char, u32)>) -> Self {
+        let items: Vec<(char, char, u32)> = items.collect();
+
+        let mut chars: Vec<char> = items.iter()
+            .flat_map(|&(shown, typed, _)| [shown, typed])
+            .collect();
+        chars.sort_unstable();
+        chars.dedup();
+
+        let mut counts = vec![vec![0; chars.len()]; chars.len()];
+        for (shown, typed, count) in items {
+            // Both searches succeed because every char was added to `chars`
+            if let (Ok(row), Ok(column)) = (chars.binary_search(&shown), chars.binary_search(&typed)) {
+                counts[row][column] += count;
+            }
+        }
+
+        ConfusionMatrix {
+            chars,
+            counts,
+        }
+    }
+
+    /// The first row and column contain the characters.
+    pub fn to_csv(&self) -> String {
+        fn push_char(string: &mut String, char: char) {
+            match char {
+                '"' => string.push_str("\"\"\"\""),
+                ',' | '\n' | '\r' => {
+                    string.push('"');
+                    string.push(char);
+                    string.push('"');
+                },
+                _ => string.push(char),
+            }
+        }
+
+        let mut string = String::new();
+        for &char in &self.chars {
+            string.push(',');
+            push_char(&mut string, char);
+        }
+        string.push_str("\r\n");
+
+        for (&char, row) in self.chars.iter().zip(&self.counts) {
+            push_char(&mut string, char);
+            for count in row {
+                string.push_str(&format!(",{}", count));
+            }
+            string.push_str("\r\n");
+        }
+
+        string
+    }
+}
+
+/// What a garbage collection deleted, or would delete in a dry run.
+#[derive(Serialize, Deserialize, Default)]
+pub struct GarbageReport {
+    pub dry_run: bool,
+    /// Versions that aren't in the history of any font
+    pub versions: u64,
+    /// Mutated glyphs that aren't used by any remaining version, queue or test, or as the parent of a remaining glyph
+    pub glyphs: u64,
+    /// `VersionGlyph`s, trials and confusions of the deleted versions, and origins of the deleted glyphs
+    pub records: u64,
+}
+
+/// The result of checking the stored data.
+#[derive(Serialize, Deserialize, Default)]
+pub struct IntegrityReport {
+    /// Whether problems were repaired where possible
+    pub repair: bool,
+    /// Items that were checked, in every tree
+    pub items: u64,
+    pub problems: Vec<Problem>,
+}
+
+/// An item that can't be decoded, or that refers to something that doesn't exist.
+#[derive(Serialize, Deserialize)]
+pub struct Problem {
+    pub tree: String,
+    /// Describes the item's key, such as "glyph 12"
+    pub item: String,
+    pub message: String,
+    /// Whether the item was removed or changed to fix the problem
+    pub repaired: bool,
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+/// A tester's results in one font, or in every font
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TesterStats {
+    /// Finished tests, including failed ones
+    pub tests: u32,
+    /// The share of tests where the correct character was typed or chosen, or `None` without tests
+    pub accuracy: Option<f64>,
+    /// Mean milliseconds of the correct tests, or `None` without any
+    pub average_time: Option<f64>,
+    /// Versions that ended with a glyph whose winning score came from the tester's fastest trial
+    pub winning_scores: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub user: u64,
+    pub name: String,
+    #[serde(flatten)]
+    pub stats: TesterStats,
+}
+
+/// Testers ranked by winning scores, then by finished tests
+#[derive(Serialize, Deserialize)]
+pub struct Leaderboard {
+    /// `None` for the leaderboard of every font
+    pub font: Option<u64>,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+/// One tester's results in every font, and in each font they took part in
+#[derive(Serialize, Deserialize)]
+pub struct UserStats {
+    pub user: u64,
+    pub name: String,
+    pub overall: TesterStats,
+    pub fonts: Vec<FontStats>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FontStats {
+    pub font: u64,
+    #[serde(flatten)]
+    pub stats: TesterStats,
+}
@@ -1,4 +1,4 @@
-use deku::bitvec::{BitVec, Msb0};
+use deku::bitvec::{BitSlice, BitVec, Msb0};
 use deku::prelude::*;
 
 pub trait DekuRW
@@ -21,9 +21,47 @@ pub fn char_map(n: u32) -> Result<char, DekuError> {
         .ok_or(DekuError::Parse("invalid_char".to_owned()))
 }
 
+// `char_map` reads the `u32` with the field's endianness, which is always big
 pub fn char_write(output: &mut BitVec<Msb0, u8>, char: &char) -> Result<(), DekuError> {
     u32::from(*char)
-        .write(output, ())
+        .write(output, deku::ctx::Endian::Big)
+}
+
+/// Reads items until there's no data left. Unlike `bits_read = "deku::rest.len()"`, this also works when there are no items.
+pub fn read_rest<'a, T, Ctx>(
+    mut rest: &'a BitSlice<Msb0, u8>,
+    ctx: Ctx,
+) -> Result<(&'a BitSlice<Msb0, u8>, Vec<T>), DekuError>
+where
+    T: DekuRead<'a, Ctx>,
+    Ctx: Copy,
+{
+    let mut items = Vec::new();
+    while !rest.is_empty() {
+        let (new_rest, item) = T::read(rest, ctx)?;
+        items.push(item);
+        rest = new_rest;
+    }
+    Ok((rest, items))
+}
+
+/// Like `read_rest`, but stops when less than `item_bits` are left, so fields shorter than an item can follow the items.
+pub fn read_whole_items<'a, T, Ctx>(
+    mut rest: &'a BitSlice<Msb0, u8>,
+    item_bits: usize,
+    ctx: Ctx,
+) -> Result<(&'a BitSlice<Msb0, u8>, Vec<T>), DekuError>
+where
+    T: DekuRead<'a, Ctx>,
+    Ctx: Copy,
+{
+    let mut items = Vec::new();
+    while rest.len() >= item_bits {
+        let (new_rest, item) = T::read(rest, ctx)?;
+        items.push(item);
+        rest = new_rest;
+    }
+    Ok((rest, items))
 }
 
 // For null-terminated strings